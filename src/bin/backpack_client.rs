@@ -1,142 +1,103 @@
 use backpack::subscrib_stream::*;
-use clap::Parser;
-use futures::channel::mpsc::Sender;
-use futures::stream::{SplitSink, SplitStream};
-use futures::{SinkExt, StreamExt};
-use native_tls::TlsConnector;
-use serde_json::Value;
-use tokio::fs::OpenOptions;
-use tokio::io::{stdin, AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
-use tokio::time::Instant;
-use tokio_tungstenite::{
-    connect_async_tls_with_config,
-    tungstenite::protocol::{Message, WebSocketConfig},
-    Connector, MaybeTlsStream, WebSocketStream,
+use backpack::{
+    BackpackClient, ClientEvent, Event, MarketRegistry, MultiSink, OrderBook, Sink, SinkSpec,
 };
-use tracing::info;
+use clap::Parser;
+use futures::StreamExt;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::{info, warn};
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt::init();
 
-    let client = redis::Client::open("redis://127.0.0.1/")?;
-    let mut con = client.get_connection()?;
-    let channel_name = "channel";
-
     let opt = Opts::parse();
     let url = opt.url;
     info!("User input url: {}", url);
-    let stream_name = StreamName::from(opt.stream_name);
-    let method = Method::from(opt.method);
+    if let Some(path) = &opt.markets {
+        MarketRegistry::from_file(path)?.install();
+        info!("Loaded markets from: {}", path.display());
+    }
+    // Parsed only now, against the markets just loaded.
+    let stream_name: StreamName = opt.stream_name.parse()?;
 
-    let (ws_stream, _) = connect_async_tls_with_config(
-        url,
-        Some(WebSocketConfig::default()),
-        false,
-        Some(Connector::NativeTls(TlsConnector::new().unwrap())),
-    )
-    .await?;
-    let (mut ws_write, mut ws_read) = ws_stream.split();
+    let mut sink = MultiSink::new();
+    for spec in &opt.sink {
+        sink = sink.with_sink(spec.open()?);
+        info!("Recording to: {:?}", spec);
+    }
 
-    let subscrib_stream = SubscribStream {
-        method,
-        params: vec![stream_name],
-    };
-    let json = serde_json::to_string(&subscrib_stream).unwrap();
-    let msg = Message::Text(json);
-    ws_write.send(msg).await?;
+    let (client, mut events) = BackpackClient::connect(&url).await?;
+    client.request(opt.method, vec![stream_name])?;
     info!("Subscribed!");
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut flush = interval(FLUSH_INTERVAL);
+    flush.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut flush_error = None;
     let mut sum = 0usize;
-    let instant = Instant::now();
-    while let Some(msg) = ws_read.next().await {
-        let message = msg?;
-        match message {
-            Message::Text(text) => {
-                sum += 1;
-                if sum % 20 == 0 {
-                    info!("Received 10 messages since last time");
+    let mut books = BTreeMap::<String, OrderBook>::new();
+    loop {
+        let event = tokio::select! {
+            event = events.next() => match event {
+                Some(event) => event,
+                None => break,
+            },
+            _ = flush.tick() => {
+                // Only report changes, a sink that is down stays down for many flushes.
+                let error = sink.flush().await.err().map(|e| e.to_string());
+                if error.is_some() && error != flush_error {
+                    warn!("Failed to flush: {}", error.as_deref().unwrap_or_default());
                 }
-                let v = serde_json::from_str::<Value>(&text)?;
-                let mut pretty_msg = serde_json::to_string_pretty(&v)?;
-                redis::cmd("PUBLISH")
-                    .arg(channel_name)
-                    .arg(pretty_msg.clone())
-                    .query(&mut con)?;
-                pretty_msg += "\n";
-                let mut file = OpenOptions::new()
-                    .create(true)
-                    .write(true)
-                    .append(true)
-                    .open("./message.json")
-                    .await?;
-                file.write_all(pretty_msg.as_bytes()).await?;
+                flush_error = error;
+                continue;
             }
-            Message::Ping(ping) => {
-                if instant.elapsed().as_secs() < 60 {
-                    info!("Received a ping message:{}", String::from_utf8_lossy(&ping));
-                    let msg = Message::Pong("Pong!".as_bytes().to_vec());
-                    ws_write.send(msg).await?;
-                    info!("Sent a pong message");
-                } else {
-                    info!("Time out, close the connection");
+            _ = tokio::signal::ctrl_c() => break,
+            _ = terminate.recv() => break,
+        };
+        let (envelope, received_at) = match event {
+            ClientEvent::Event {
+                envelope,
+                received_at,
+            } => (envelope, received_at),
+            ClientEvent::Gap { streams } => {
+                warn!("Possible gap in {:?}, resyncing order books", streams);
+                for stream in streams {
+                    books.remove(&stream.to_string());
                 }
+                continue;
             }
-            _ => {}
-        }
-    }
-    Ok(())
-}
-
-pub async fn read_message(
-    mut ws_read: SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>,
-    mut ws_write: SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>,
-) -> anyhow::Result<()> {
-    let mut sum = 0usize;
-    while let Some(msg) = ws_read.next().await {
-        let message = msg?;
-        match message {
-            Message::Text(text) => {
-                sum += 1;
-                if sum % 10 == 0 {
-                    info!("Received 10 messages since last time");
-                }
-                let v = serde_json::from_str::<Value>(&text)?;
-                let mut pretty_msg = serde_json::to_string_pretty(&v)?;
-                pretty_msg += "\n";
-                let mut file = OpenOptions::new()
-                    .create(true)
-                    .write(true)
-                    .append(true)
-                    .open("./message.json")
-                    .await?;
-                file.write_all(pretty_msg.as_bytes()).await?;
+            other => {
+                info!("Received a client event: {:?}", other);
+                continue;
             }
-            Message::Ping(ping) => {
-                info!("Received a ping message:{}", String::from_utf8_lossy(&ping));
-                let msg = Message::Pong("Pong!".as_bytes().to_vec());
-                ws_write.send(msg).await?;
-                info!("Sent a pong message");
+        };
+        sum += 1;
+        if sum.is_multiple_of(20) {
+            info!("Received 10 messages since last time");
+        }
+        if let Event::Depth(depth) = &envelope.data {
+            let book = books.entry(envelope.stream.clone()).or_default();
+            if let Err(e) = book.apply(depth) {
+                warn!("Order book {} is out of sync: {}", envelope.stream, e);
+                book.clear();
             }
-            _ => {}
+        }
+        // A sink that fails, say a Redis that went away, must not stop the recording.
+        if let Err(e) = sink.write(&envelope, received_at).await {
+            warn!("Failed to record {}: {}", envelope.stream, e);
         }
     }
+    info!("Shutting down after {} messages", sum);
+    sink.close().await?;
     Ok(())
 }
 
-pub async fn read_stdin(mut sender: Sender<String>) -> anyhow::Result<()> {
-    let mut stdin = stdin();
-
-    loop {
-        let mut buf = vec![0; 1024];
-
-        let n = stdin.read(&mut buf).await?;
-        buf.truncate(n);
-        let msg = String::from_utf8(buf).unwrap();
-        sender.send(msg).await?;
-    }
-}
-
 #[derive(Parser, Debug)]
 pub struct Opts {
     #[clap(short, long, default_value = "wss://ws.backpack.exchange")]
@@ -144,5 +105,15 @@ pub struct Opts {
     #[clap(short, long, default_value = "depth.SOL_USDC")]
     stream_name: String,
     #[clap(short, long, default_value = "SUBSCRIBE")]
-    method: String,
+    method: Method,
+    /// JSON array of markets to accept stream names for, instead of the built-in ones.
+    #[clap(long)]
+    markets: Option<PathBuf>,
+    /// Where to record events, repeat to record to several: stdout, file:<path> (`{stream}` is
+    /// replaced by the stream name), rotate:<options>:<prefix> (options are comma separated:
+    /// a size limit in MB, an age limit like 30s, 15m or 1h, and gzip or zstd to compress
+    /// finished files), redis:<url>[#<channel>] (`backpack:{stream}` by default),
+    /// redis-stream:<url>[#<max len>] (one stream per market) or memory:<capacity>.
+    #[clap(long, default_value = "file:./{stream}.ndjson")]
+    sink: Vec<SinkSpec>,
 }
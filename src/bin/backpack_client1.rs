@@ -1,13 +1,6 @@
-use futures::{SinkExt, StreamExt};
-use native_tls::TlsConnector;
-use serde_json::Value;
-use tokio::fs::OpenOptions;
-use tokio::io::AsyncWriteExt;
-use tokio_tungstenite::{
-    connect_async_tls_with_config,
-    tungstenite::protocol::{Message, WebSocketConfig},
-    Connector,
-};
+use backpack::{BackpackClient, ClientEvent, Compression, RotatingFileSink, Sink};
+use futures::StreamExt;
+use std::time::Duration;
 use tracing::info;
 
 #[tokio::main]
@@ -17,47 +10,35 @@ async fn main() -> anyhow::Result<()> {
         .nth(1)
         .unwrap_or_else(|| "wss://ws.backpack.exchange".to_string());
 
-    let (ws_stream, _) = connect_async_tls_with_config(
-        url,
-        Some(WebSocketConfig::default()),
-        false,
-        Some(Connector::NativeTls(TlsConnector::new().unwrap())),
-    )
-    .await?;
-    let (mut ws_write, mut ws_read) = ws_stream.split();
-
-    let subscribe_msg = r#"{"method":"SUBSCRIBE","params":["depth.SOL_USDC"]}"#.to_string();
-    let msg = Message::Text(subscribe_msg);
-    ws_write.send(msg).await?;
+    let (client, mut events) = BackpackClient::connect(&url).await?;
+    client.subscribe(vec!["depth.SOL_USDC".parse()?])?;
     info!("Subscribed to the depth.SOL_USDC channel");
+    // An hour per file, gzipped once it is done with.
+    let mut sink = RotatingFileSink::new("./depth.SOL_USDC")
+        .with_max_age(Duration::from_secs(60 * 60))
+        .with_compression(Compression::Gzip);
     let mut sum = 0usize;
-    while let Some(msg) = ws_read.next().await {
-        let message = msg?;
-        match message {
-            Message::Text(text) => {
-                sum += 1;
-                if sum % 1000 == 0 {
-                    info!("Received 1000 messages since last time");
-                }
-                let v = serde_json::from_str::<Value>(&text)?;
-                let mut pretty_msg = serde_json::to_string_pretty(&v)?;
-                pretty_msg += "\n";
-                let mut file = OpenOptions::new()
-                    .create(true)
-                    .write(true)
-                    .append(true)
-                    .open("./message.json")
-                    .await?;
-                file.write_all(pretty_msg.as_bytes()).await?;
-            }
-            Message::Ping(ping) => {
-                info!("Received a ping message:{}", String::from_utf8_lossy(&ping));
-                let msg = Message::Pong("Pong!".as_bytes().to_vec());
-                ws_write.send(msg).await?;
-                info!("Sent a pong message");
-            }
-            _ => {}
+    loop {
+        let event = tokio::select! {
+            event = events.next() => event,
+            _ = tokio::signal::ctrl_c() => None,
+        };
+        let Some(event) = event else {
+            break;
+        };
+        let ClientEvent::Event {
+            envelope,
+            received_at,
+        } = event
+        else {
+            continue;
+        };
+        sum += 1;
+        if sum.is_multiple_of(1000) {
+            info!("Received 1000 messages since last time");
         }
+        sink.write(&envelope, received_at).await?;
     }
+    sink.close().await?;
     Ok(())
 }
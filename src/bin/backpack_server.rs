@@ -1,172 +1,712 @@
-use backpack::parse_stream_name;
 use backpack::subscrib_stream::*;
-use backpack::UpdataStream;
+use backpack::{
+    Clock, DepthSnapshot, ErrorCode, Heartbeat, HeartbeatAction, HubError, MarketRegistry,
+    Response, ResponseError, ServerConfig, SimulatedClock, SimulationConfig, StreamHub,
+    SystemClock,
+};
+use clap::Parser;
 use futures::channel::mpsc::{channel, Receiver, Sender};
 use futures::stream::{SplitSink, SplitStream};
 use futures::{SinkExt, StreamExt};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::Mutex;
-use tokio::time::{sleep, Instant};
-use tokio_tungstenite::{accept_async, tungstenite::Message, WebSocketStream};
-use tracing::info;
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use tokio_tungstenite::tungstenite::error::ProtocolError;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::{self, Message};
+use tokio_tungstenite::{accept_async, WebSocketStream};
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use tracing::{info, warn};
+
+const MAX_HTTP_REQUEST: usize = 8 * 1024;
+
+// How long a closing session gets to flush its close frame.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+type Subscriptions = Arc<Mutex<BTreeMap<String, (StreamName, JoinHandle<()>)>>>;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     // Enable logging
     tracing_subscriber::fmt::init();
-    let addr = std::env::args()
-        .nth(1)
-        .unwrap_or_else(|| "127.0.0.1:8080".to_string());
-    let listener = TcpListener::bind(&addr).await?;
-    info!("Listening on: {}", addr);
-
-    while let Ok((stream, _)) = listener.accept().await {
-        info!("Accepted connection from: {}", stream.peer_addr()?);
-        tokio::spawn(process(stream));
+    let opt = Opts::parse();
+    let config = Arc::new(opt.config()?);
+    config.registry().install();
+    // A replay plays recorded timestamps, so there is no simulated clock to set up for it.
+    if config.replay.is_some() && (opt.start_time.is_some() || opt.speed.is_some()) {
+        anyhow::bail!("--start-time and --speed only apply to the simulation, use --replay-speed");
+    }
+    let (clock, speed): (Arc<dyn Clock>, f64) = match (opt.start_time, opt.speed) {
+        (None, None) => (Arc::new(SystemClock), 1.0),
+        (start_time, speed) => {
+            let start_time = start_time.unwrap_or_else(|| SystemClock.now_micros());
+            let speed = speed.unwrap_or(1.0);
+            if !(speed.is_finite() && speed > 0.0) {
+                anyhow::bail!("--speed must be a positive number, got {}", speed);
+            }
+            (Arc::new(SimulatedClock::scaled(start_time, speed)), speed)
+        }
+    };
+    // Simulated time runs `speed` times faster, so markets step faster to keep up with it, or
+    // cover more of it per step once they step every millisecond.
+    let mut hub = StreamHub::new(
+        config.simulation.clone(),
+        clock,
+        config.tick(),
+        config.limits.stream_capacity,
+    )
+    .with_speed(speed);
+    if let Some(replay) = &config.replay {
+        info!(
+            "Replaying {} files instead of simulating",
+            replay.files.len()
+        );
+        hub = hub.with_replay(replay.clone());
     }
+    let hub = Arc::new(hub);
+    if let Some(http_addr) = &config.http_listen {
+        let listener = TcpListener::bind(http_addr).await?;
+        info!("Serving depth snapshots on: {}", http_addr);
+        tokio::spawn(serve_http(listener, hub.clone()));
+    }
+    let listener = TcpListener::bind(&config.listen).await?;
+    info!("Listening on: {}", config.listen);
 
+    let shutdown = CancellationToken::new();
+    tokio::spawn(cancel_on_signal(shutdown.clone()));
+    let sessions = TaskTracker::new();
+    loop {
+        let (stream, peer_addr) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!("Failed to accept a connection: {}", e);
+                    break;
+                }
+            },
+            _ = shutdown.cancelled() => break,
+        };
+        info!("Accepted connection from: {}", peer_addr);
+        if config
+            .limits
+            .max_connections
+            .is_some_and(|max| sessions.len() >= max)
+        {
+            warn!("Too many connections, turning away: {}", peer_addr);
+            tokio::spawn(reject(stream));
+            continue;
+        }
+        let session = process(
+            stream,
+            peer_addr,
+            hub.clone(),
+            config.clone(),
+            shutdown.clone(),
+        );
+        sessions.spawn(async move {
+            if let Err(e) = session.await {
+                warn!("Session with {} failed: {}", peer_addr, e);
+            }
+        });
+    }
+    // Sessions see the same token, so they close themselves; this only waits for them.
+    shutdown.cancel();
+    sessions.close();
+    info!("Draining {} sessions", sessions.len());
+    if timeout(DRAIN_TIMEOUT, sessions.wait()).await.is_err() {
+        warn!("{} sessions did not close in time", sessions.len());
+    }
     Ok(())
 }
 
-async fn process(stream: TcpStream) -> anyhow::Result<()> {
-    let peer_addr = stream.peer_addr()?;
-    let ws_stream = accept_async(stream)
-        .await
-        .map_err(|e| anyhow::anyhow!("Error during WebSocket handshake: {}", e))?;
+async fn cancel_on_signal(shutdown: CancellationToken) {
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(e) => {
+            warn!("Failed to listen for SIGTERM: {}", e);
+            return;
+        }
+    };
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => info!("Received SIGINT, shutting down"),
+        _ = terminate.recv() => info!("Received SIGTERM, shutting down"),
+    }
+    shutdown.cancel();
+}
+
+#[derive(Debug)]
+pub enum SessionEnd {
+    PeerClosed(Option<CloseFrame<'static>>),
+    // The TCP connection went away without a close handshake.
+    PeerGone,
+    ReadFailed(tungstenite::Error),
+    WriteFailed,
+    TimedOut,
+    Failed(anyhow::Error),
+    Shutdown,
+}
+
+impl SessionEnd {
+    // A peer that dropped or reset the connection is gone, there is nobody to send a close to.
+    fn read_failed(error: tungstenite::Error) -> Self {
+        match error {
+            tungstenite::Error::Protocol(ProtocolError::ResetWithoutClosingHandshake)
+            | tungstenite::Error::ConnectionClosed
+            | tungstenite::Error::AlreadyClosed => SessionEnd::PeerGone,
+            tungstenite::Error::Io(e) if e.kind() == io::ErrorKind::ConnectionReset => {
+                SessionEnd::PeerGone
+            }
+            error => SessionEnd::ReadFailed(error),
+        }
+    }
+
+    // The close frame to send the peer, if the connection is still usable and the peer has
+    // not closed it already. A close from the peer is answered by tungstenite itself.
+    fn close_frame(&self) -> Option<Message> {
+        let (code, reason) = match self {
+            SessionEnd::PeerClosed(_) | SessionEnd::PeerGone | SessionEnd::WriteFailed => {
+                return None
+            }
+            SessionEnd::ReadFailed(_) => (CloseCode::Protocol, "protocol error"),
+            SessionEnd::TimedOut => return Some(Heartbeat::close_frame()),
+            SessionEnd::Failed(_) => (CloseCode::Error, "internal error"),
+            SessionEnd::Shutdown => (CloseCode::Away, "server shutting down"),
+        };
+        Some(Message::Close(Some(CloseFrame {
+            code,
+            reason: reason.into(),
+        })))
+    }
+}
+
+impl Display for SessionEnd {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionEnd::PeerClosed(Some(frame)) => {
+                write!(f, "closed by the peer ({}: {})", frame.code, frame.reason)
+            }
+            SessionEnd::PeerClosed(None) => write!(f, "closed by the peer"),
+            SessionEnd::PeerGone => write!(f, "connection dropped by the peer"),
+            SessionEnd::ReadFailed(e) => write!(f, "read failed: {}", e),
+            SessionEnd::WriteFailed => write!(f, "write failed"),
+            SessionEnd::TimedOut => write!(f, "heartbeat timed out"),
+            SessionEnd::Failed(e) => write!(f, "{}", e),
+            SessionEnd::Shutdown => write!(f, "server shutting down"),
+        }
+    }
+}
+
+// Completes the handshake only to tell the client why it is being closed.
+async fn reject(stream: TcpStream) {
+    if let Ok(mut ws_stream) = accept_async(stream).await {
+        let _ = ws_stream
+            .close(Some(CloseFrame {
+                code: CloseCode::Again,
+                reason: "too many connections".into(),
+            }))
+            .await;
+    }
+}
+
+// One session per connection. Whatever ends it, the peer closing, a failed read or write,
+// a heartbeat timeout or a server shutdown, stops every task of the session together.
+async fn process(
+    stream: TcpStream,
+    peer_addr: SocketAddr,
+    hub: Arc<StreamHub>,
+    config: Arc<ServerConfig>,
+    shutdown: CancellationToken,
+) -> anyhow::Result<()> {
+    let ws_stream = tokio::select! {
+        ws_stream = accept_async(stream) => ws_stream
+            .map_err(|e| anyhow::anyhow!("Error during WebSocket handshake: {}", e))?,
+        _ = shutdown.cancelled() => return Ok(()),
+    };
     info!("WebSocket connection established with: {:?}", peer_addr);
-    let (tx, rx) = channel::<Message>(1000);
-    let (in_tx, in_rx) = broadcast::channel(5);
-    let stream_names = Arc::new(Mutex::new(HashMap::<String, Box<dyn UpdataStream>>::new()));
-    let stream_names_handle = tokio::spawn(updata_stream(
-        stream_names.clone(),
-        tx.clone(),
-        in_tx.subscribe(),
-    ));
-    let send_ping_handle = tokio::spawn(send_ping(tx.clone(), in_tx.subscribe()));
+    let (mut tx, rx) = channel::<Message>(config.limits.send_buffer);
+    let subscriptions = Subscriptions::default();
+    let write_failed = CancellationToken::new();
     let (write, read) = ws_stream.split();
-    let send_message_handle = tokio::spawn(send_message(rx, write, in_rx));
-    let read_message_handle = tokio::spawn(read_message(stream_names, read, in_tx));
-    let _ = tokio::join!(
-        read_message_handle,
-        send_message_handle,
-        stream_names_handle,
-        send_ping_handle
+    let mut send_message_handle = tokio::spawn(send_message(rx, write, write_failed.clone()));
+    let end = read_message(
+        &hub,
+        &config,
+        &subscriptions,
+        read,
+        &mut tx,
+        &shutdown,
+        &write_failed,
+    )
+    .await;
+    let names = subscriptions.lock().await.keys().cloned().collect();
+    unsubscribe(names, &hub, &subscriptions).await;
+    if let Some(frame) = end.close_frame() {
+        let _ = tx.send(frame).await;
+    }
+    drop(tx);
+    if timeout(CLOSE_TIMEOUT, &mut send_message_handle)
+        .await
+        .is_err()
+    {
+        send_message_handle.abort();
+    }
+    info!("WebSocket connection closed with: {:?}, {}", peer_addr, end);
+    Ok(())
+}
+
+async fn serve_http(listener: TcpListener, hub: Arc<StreamHub>) {
+    while let Ok((stream, peer_addr)) = listener.accept().await {
+        let hub = hub.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_http(stream, &hub).await {
+                warn!("HTTP request from {} failed: {}", peer_addr, e);
+            }
+        });
+    }
+}
+
+// Just enough HTTP/1.1 for `GET /api/v1/depth?symbol=...`, one request per connection.
+async fn handle_http(mut stream: TcpStream, hub: &StreamHub) -> anyhow::Result<()> {
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 || request.len() > MAX_HTTP_REQUEST {
+            anyhow::bail!("incomplete HTTP request");
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let request_line = request.lines().next().unwrap_or_default();
+    info!("Received an HTTP request: {}", request_line);
+    let (status, body) = match depth_snapshot(request_line, hub) {
+        Ok(snapshot) => ("200 OK", serde_json::to_string(&snapshot)?),
+        Err((status, error)) => (status, serde_json::to_string(&error)?),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
     );
-    info!("WebSocket connection closed with: {:?}", peer_addr);
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
     Ok(())
 }
 
+fn depth_snapshot(
+    request_line: &str,
+    hub: &StreamHub,
+) -> Result<DepthSnapshot, (&'static str, ResponseError)> {
+    let error = |status, code, message: String| Err((status, ResponseError { code, message }));
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = (parts.next(), parts.next().unwrap_or_default());
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    if path != "/api/v1/depth" {
+        return error(
+            "404 Not Found",
+            ErrorCode::NotFound,
+            format!("no route for {}", path),
+        );
+    }
+    if method != Some("GET") {
+        let message = "only GET is supported".to_string();
+        return error(
+            "405 Method Not Allowed",
+            ErrorCode::MalformedRequest,
+            message,
+        );
+    }
+    let symbol = query
+        .split('&')
+        .filter_map(|param| param.split_once('='))
+        .find_map(|(key, value)| (key == "symbol").then_some(value));
+    let Some(symbol) = symbol else {
+        let message = "missing symbol".to_string();
+        return error("400 Bad Request", ErrorCode::MalformedRequest, message);
+    };
+    match symbol
+        .parse()
+        .and_then(|symbol| hub.depth_snapshot(&symbol))
+    {
+        Ok(snapshot) => Ok(snapshot),
+        Err(e) => error("400 Bad Request", ErrorCode::InvalidSymbol, e.to_string()),
+    }
+}
+
 pub async fn subscribe(
-    params: Vec<StreamName>,
-    stream_names: Arc<Mutex<HashMap<String, Box<dyn UpdataStream>>>>,
+    id: Option<u64>,
+    mut params: Vec<StreamName>,
+    hub: &StreamHub,
+    subscriptions: &Subscriptions,
+    tx: &mut Sender<Message>,
+    max_subscriptions: Option<usize>,
 ) -> anyhow::Result<()> {
-    let mut stream_names = stream_names.lock().await;
+    let mut subscriptions = subscriptions.lock().await;
     info!("Subscribe to stream: {:?}", params);
-    for param in params {
-        stream_names
-            .entry(param.to_string())
-            .or_insert_with(|| (parse_stream_name(param.clone())) as _);
+    let mut seen = BTreeSet::new();
+    params.retain(|param| seen.insert(param.to_string()));
+    if let Some(param) = params
+        .iter()
+        .find(|param| subscriptions.contains_key(&param.to_string()))
+    {
+        let message = format!("already subscribed to {}", param);
+        let response = Response::error(id, ErrorCode::AlreadySubscribed, message);
+        return send_response(tx, &response).await;
+    }
+    if let Some(max) = max_subscriptions.filter(|&max| subscriptions.len() + params.len() > max) {
+        let message = format!("at most {} subscriptions per connection", max);
+        let response = Response::error(id, ErrorCode::TooManySubscriptions, message);
+        return send_response(tx, &response).await;
+    }
+    // Hold the receivers until the ack is queued, so it always arrives before the first update.
+    let mut receivers = Vec::new();
+    for param in &params {
+        match hub.subscribe(param) {
+            Ok(rx) => receivers.push(rx),
+            Err(e) => {
+                params[..receivers.len()]
+                    .iter()
+                    .for_each(|param| hub.unsubscribe(param));
+                let code = match e {
+                    HubError::ReplayFinished => ErrorCode::ReplayFinished,
+                    HubError::Parse(_) => ErrorCode::InvalidStream,
+                };
+                let response = Response::error(id, code, e.to_string());
+                return send_response(tx, &response).await;
+            }
+        }
+    }
+    send_response(tx, &Response::success(id, params.clone())).await?;
+    for (param, rx) in params.into_iter().zip(receivers) {
+        let handle = tokio::spawn(forward_stream(rx, tx.clone()));
+        subscriptions.insert(param.to_string(), (param, handle));
     }
     Ok(())
 }
 
-pub async fn unsubscribe(
-    params: Vec<StreamName>,
-    stream_names: Arc<Mutex<HashMap<String, Box<dyn UpdataStream>>>>,
-) -> anyhow::Result<()> {
-    let mut stream_names = stream_names.lock().await;
+pub async fn unsubscribe(params: Vec<String>, hub: &StreamHub, subscriptions: &Subscriptions) {
+    let mut subscriptions = subscriptions.lock().await;
     for param in params {
-        stream_names.remove(&param.to_string());
+        if let Some((stream_name, handle)) = subscriptions.remove(&param) {
+            handle.abort();
+            hub.unsubscribe(&stream_name);
+        }
     }
+}
+
+pub async fn send_response(tx: &mut Sender<Message>, response: &Response) -> anyhow::Result<()> {
+    if let Some(error) = &response.error {
+        info!("Rejected a request: {:?} {}", error.code, error.message);
+    }
+    tx.send(Message::Text(serde_json::to_string(response)?))
+        .await?;
     Ok(())
 }
 
-pub async fn updata_stream(
-    stream_names: Arc<Mutex<HashMap<String, Box<dyn UpdataStream>>>>,
-    mut tx: Sender<Message>,
-    mut in_rx: broadcast::Receiver<Message>,
-) -> anyhow::Result<()> {
+pub async fn forward_stream(mut rx: broadcast::Receiver<Message>, mut tx: Sender<Message>) {
     loop {
-        let instant = Instant::now();
-        sleep(Duration::from_secs(1)).await;
-        let mut stream_names = stream_names.lock().await;
-        for (_, stream) in stream_names.iter_mut() {
-            let rng = rand::thread_rng();
-            stream.update(instant.elapsed().as_secs(), rng);
-            let message = stream.to_message();
-            tx.send(message).await?;
-        }
-        if in_rx.try_recv().is_ok() {
-            break;
+        match rx.recv().await {
+            Ok(message) => {
+                if tx.send(message).await.is_err() {
+                    break;
+                }
+            }
+            Err(RecvError::Lagged(skipped)) => {
+                warn!("Subscriber lagged, skipped {} messages", skipped)
+            }
+            Err(RecvError::Closed) => break,
         }
     }
-    Ok(())
 }
 
+// Writes until the session drops its senders or a close frame has gone out.
 pub async fn send_message(
     mut rx: Receiver<Message>,
     mut write: SplitSink<WebSocketStream<TcpStream>, Message>,
-    mut in_rx: broadcast::Receiver<Message>,
+    write_failed: CancellationToken,
 ) {
     while let Some(msg) = rx.next().await {
-        write.send(msg).await.unwrap();
-        if in_rx.try_recv().is_ok() {
-            break;
+        // Nothing may follow a close frame.
+        let closing = matches!(msg, Message::Close(_));
+        if let Err(e) = write.send(msg).await {
+            warn!("Failed to write a message: {}", e);
+            write_failed.cancel();
+            return;
         }
-    }
-}
-
-pub async fn send_ping(mut tx: Sender<Message>, mut in_rx: broadcast::Receiver<Message>) {
-    loop {
-        let msg = Message::Ping("Ping!".as_bytes().to_vec());
-        info!("Send a ping message");
-        tx.send(msg).await.unwrap();
-        sleep(Duration::from_secs(10)).await;
-        if in_rx.try_recv().is_ok() {
+        if closing {
             break;
         }
     }
+    let _ = write.close().await;
 }
 
 pub async fn read_message(
-    stream_names: Arc<Mutex<HashMap<String, Box<dyn UpdataStream>>>>,
+    hub: &StreamHub,
+    config: &ServerConfig,
+    subscriptions: &Subscriptions,
     mut read: SplitStream<WebSocketStream<TcpStream>>,
-    in_tx: broadcast::Sender<Message>,
-) -> anyhow::Result<()> {
-    let mut instant0 = Instant::now();
-    while let Some(msg) = read.next().await {
-        let now = Instant::now();
-        if now.duration_since(instant0).as_secs() > 20 {
-            info!("Time out, close the connection");
-            in_tx.send(Message::Close(None))?;
-            break;
-        }
-        let names = stream_names.clone();
-        let message = msg?;
+    tx: &mut Sender<Message>,
+    shutdown: &CancellationToken,
+    write_failed: &CancellationToken,
+) -> SessionEnd {
+    let mut heartbeat = Heartbeat::new((&config.heartbeat).into());
+    let max_subscriptions = config.limits.max_subscriptions;
+    loop {
+        let message = tokio::select! {
+            action = heartbeat.tick() => {
+                let ping = match action {
+                    HeartbeatAction::Ping(ping) => ping,
+                    HeartbeatAction::TimedOut => return SessionEnd::TimedOut,
+                };
+                if tx.send(ping).await.is_err() {
+                    return SessionEnd::WriteFailed;
+                }
+                continue;
+            }
+            message = read.next() => match message {
+                Some(Ok(message)) => message,
+                Some(Err(e)) => return SessionEnd::read_failed(e),
+                None => return SessionEnd::PeerGone,
+            },
+            _ = shutdown.cancelled() => return SessionEnd::Shutdown,
+            _ = write_failed.cancelled() => return SessionEnd::WriteFailed,
+        };
         match message {
             Message::Text(text) => {
-                info!("Received a text message: {}", text);
-                let subscrib_stream = serde_json::from_str::<SubscribStream>(&text)?;
-                match subscrib_stream.method {
-                    Method::Subscribe => subscribe(subscrib_stream.params, names).await?,
-                    Method::Unsubscribe => unsubscribe(subscrib_stream.params, names).await?,
+                if let Err(e) =
+                    handle_request(&text, hub, subscriptions, tx, max_subscriptions).await
+                {
+                    return SessionEnd::Failed(e);
                 }
             }
-            Message::Pong(pong) => {
-                if String::from_utf8_lossy(&pong) == "Pong!" {
-                    info!(
-                        "Received a pong message: {}",
-                        String::from_utf8_lossy(&pong)
-                    );
-                    instant0 += Instant::now() - instant0;
+            Message::Pong(_) => {
+                if let Some(round_trip) = heartbeat.on_message(&message) {
+                    info!("Heartbeat round trip: {:?}", round_trip);
                 }
             }
+            Message::Close(frame) => return SessionEnd::PeerClosed(frame),
             _ => {}
         }
     }
+}
+
+async fn handle_request(
+    text: &str,
+    hub: &StreamHub,
+    subscriptions: &Subscriptions,
+    tx: &mut Sender<Message>,
+    max_subscriptions: Option<usize>,
+) -> anyhow::Result<()> {
+    info!("Received a text message: {}", text);
+    let request = match SubscribStream::from_request(text) {
+        Ok(request) => request,
+        Err(response) => {
+            send_response(tx, &response).await?;
+            return Ok(());
+        }
+    };
+    match request.method {
+        Method::Subscribe => {
+            subscribe(
+                request.id,
+                request.params,
+                hub,
+                subscriptions,
+                tx,
+                max_subscriptions,
+            )
+            .await?
+        }
+        Method::Unsubscribe => {
+            let names: Vec<String> = request.params.iter().map(|p| p.to_string()).collect();
+            let missing = {
+                let subscriptions = subscriptions.lock().await;
+                names
+                    .iter()
+                    .find(|name| !subscriptions.contains_key(*name))
+                    .cloned()
+            };
+            let response = match missing {
+                Some(name) => {
+                    let message = format!("not subscribed to {}", name);
+                    Response::error(request.id, ErrorCode::NotSubscribed, message)
+                }
+                None => {
+                    unsubscribe(names, hub, subscriptions).await;
+                    Response::success(request.id, request.params)
+                }
+            };
+            send_response(tx, &response).await?;
+        }
+    }
     Ok(())
 }
+
+// Options given on the command line override the config file.
+#[derive(Parser, Debug)]
+pub struct Opts {
+    /// Address to listen on, `listen` in the config.
+    addr: Option<String>,
+    /// TOML or JSON server config, see `ServerConfig`.
+    #[clap(short, long)]
+    config: Option<PathBuf>,
+    /// JSON array of markets, replacing `markets` in the config.
+    #[clap(short, long)]
+    markets: Option<PathBuf>,
+    /// JSON simulation settings, replacing `simulation` in the config.
+    #[clap(short, long)]
+    simulation: Option<PathBuf>,
+    #[clap(long)]
+    seed: Option<u64>,
+    /// Start the simulated clock at this Unix time in microseconds instead of now.
+    #[clap(long)]
+    start_time: Option<u64>,
+    /// Run the simulated clock this many times faster than real time.
+    #[clap(long)]
+    speed: Option<f64>,
+    /// Serve streams from this recording instead of simulating them, repeat to play several
+    /// files one after another. Plain, `.gz` and `.zst` files are read; only markets in the
+    /// registry can be subscribed to.
+    #[clap(long)]
+    replay: Vec<PathBuf>,
+    /// Play the replay this many times faster than it was recorded.
+    #[clap(long)]
+    replay_speed: Option<f64>,
+    /// Replay as fast as subscribers take events instead of at the recorded pace.
+    #[clap(long)]
+    replay_max_speed: bool,
+    /// Start the replay over once it reaches the end.
+    #[clap(long)]
+    replay_loop: bool,
+    /// Skip events recorded before this Unix time in microseconds.
+    #[clap(long)]
+    replay_from: Option<u64>,
+    /// How far the engine timestamp `T` trails the event time `E`, in microseconds.
+    #[clap(long)]
+    engine_lag_us: Option<u64>,
+    /// Also serve REST depth snapshots (`GET /api/v1/depth?symbol=`) on this address.
+    #[clap(long)]
+    http_addr: Option<String>,
+    /// Milliseconds between two steps of every market.
+    #[clap(long)]
+    tick_ms: Option<u64>,
+    /// Seconds between pings sent to each client.
+    #[clap(long)]
+    ping_interval: Option<u64>,
+    /// Close a connection whose ping has gone unanswered for this many seconds.
+    #[clap(long)]
+    pong_timeout: Option<u64>,
+    #[clap(long)]
+    max_connections: Option<usize>,
+}
+
+impl Opts {
+    fn config(&self) -> anyhow::Result<ServerConfig> {
+        let mut config = match &self.config {
+            Some(path) => {
+                let config = ServerConfig::from_file(path)?;
+                info!("Loaded config from: {}", path.display());
+                config
+            }
+            None => ServerConfig::default(),
+        };
+        if let Some(path) = &self.markets {
+            let registry = MarketRegistry::from_file(path)?;
+            config.markets = Some(registry.markets().cloned().collect());
+            info!("Loaded markets from: {}", path.display());
+        }
+        if let Some(path) = &self.simulation {
+            config.simulation = SimulationConfig::from_file(path)?;
+        }
+        if let Some(addr) = &self.addr {
+            config.listen = addr.clone();
+        }
+        if self.http_addr.is_some() {
+            config.http_listen = self.http_addr.clone();
+        }
+        if self.seed.is_some() {
+            config.simulation.seed = self.seed;
+        }
+        if let Some(engine_lag) = self.engine_lag_us {
+            config.simulation.engine_lag_micros = engine_lag;
+        }
+        if let Some(tick) = self.tick_ms {
+            config.tick_millis = tick;
+        }
+        if let Some(interval) = self.ping_interval {
+            config.heartbeat.ping_interval_secs = interval;
+        }
+        if let Some(timeout) = self.pong_timeout {
+            config.heartbeat.pong_timeout_secs = timeout;
+        }
+        if self.max_connections.is_some() {
+            config.limits.max_connections = self.max_connections;
+        }
+        if !self.replay.is_empty()
+            || self.replay_speed.is_some()
+            || self.replay_max_speed
+            || self.replay_loop
+            || self.replay_from.is_some()
+        {
+            let replay = config.replay.get_or_insert_with(Default::default);
+            if !self.replay.is_empty() {
+                replay.files = self.replay.clone();
+            }
+            if let Some(speed) = self.replay_speed {
+                replay.speed = speed;
+            }
+            replay.max_speed |= self.replay_max_speed;
+            replay.looped |= self.replay_loop;
+            if self.replay_from.is_some() {
+                replay.from_micros = self.replay_from;
+            }
+        }
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dropped_connection_gets_no_close_frame() {
+        let gone = [
+            tungstenite::Error::Protocol(ProtocolError::ResetWithoutClosingHandshake),
+            tungstenite::Error::ConnectionClosed,
+            tungstenite::Error::AlreadyClosed,
+            tungstenite::Error::Io(io::ErrorKind::ConnectionReset.into()),
+        ];
+        for error in gone {
+            let end = SessionEnd::read_failed(error);
+            assert!(matches!(end, SessionEnd::PeerGone), "{:?}", end);
+            assert!(end.close_frame().is_none());
+        }
+    }
+
+    #[test]
+    fn protocol_violation_is_answered_with_a_close_frame() {
+        let error = tungstenite::Error::Protocol(ProtocolError::NonZeroReservedBits);
+        let end = SessionEnd::read_failed(error);
+        assert!(matches!(end, SessionEnd::ReadFailed(_)));
+        assert!(end.close_frame().is_some());
+    }
+}
@@ -1,8 +1,7 @@
-use futures_util::{future, pin_mut, StreamExt};
-use tokio::io::{stdin, stdout, AsyncReadExt, AsyncWriteExt};
-use tokio::sync::mpsc::{channel, Sender};
-use tokio_stream::wrappers::ReceiverStream;
-use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
+use backpack::subscrib_stream::*;
+use backpack::{BackpackClient, ClientEvent, MarketRegistry};
+use futures_util::StreamExt;
+use tokio::io::{stdin, stdout, AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tracing::info;
 
 #[tokio::main]
@@ -11,43 +10,52 @@ async fn main() -> anyhow::Result<()> {
     let url = std::env::args()
         .nth(1)
         .unwrap_or_else(|| panic!("Please provide a URL"));
+    // An optional JSON array of markets to accept stream names for.
+    if let Some(path) = std::env::args().nth(2) {
+        MarketRegistry::from_file(&path)?.install();
+        info!("Loaded markets from: {}", path);
+    }
     info!("User input url: {}", url);
 
-    let (tx, rx) = channel(32);
-    tokio::spawn(read_stdin(tx));
-    info!("Spawned read_stdin task");
-    info!("Is ready for WebSocket connecting");
-    let (ws_stream, _) = connect_async(url).await?;
+    let (client, mut events) = BackpackClient::connect(&url).await?;
     info!("Connected to WebSocket");
-    let (write, read) = ws_stream.split();
-
-    let ws_to_stdout = read.for_each(|message| async {
-        let data = message.unwrap().into_data();
-        info!("Received a message from WebSocket");
-        stdout().write_all(&data).await.unwrap();
-        info!("Wrote message to stdout");
-    });
-
-    let stdin_to_ws = ReceiverStream::from(rx).map(Ok).forward(write);
+    tokio::spawn(read_stdin(client));
 
-    pin_mut!(ws_to_stdout, stdin_to_ws);
-    future::select(ws_to_stdout, stdin_to_ws).await;
+    while let Some(event) = events.next().await {
+        let mut line = match event {
+            ClientEvent::Event { envelope, .. } => serde_json::to_string(&envelope)?,
+            ClientEvent::Response(response) => serde_json::to_string(&response)?,
+            other => format!("{:?}", other),
+        };
+        line += "\n";
+        stdout().write_all(line.as_bytes()).await?;
+    }
 
     Ok(())
 }
 
-async fn read_stdin(sender: Sender<Message>) -> anyhow::Result<()> {
-    let mut stdin = stdin();
+// Each line is a request such as `SUBSCRIBE depth.SOL_USDC trade.SOL_USDC`.
+async fn read_stdin(client: BackpackClient) -> anyhow::Result<()> {
+    let mut lines = BufReader::new(stdin()).lines();
     info!("Reading is Ready");
-    let mut account = 0;
-    loop {
-        account += 1;
-        info!("Reading from stdin: {}", account);
-        let mut buf = vec![0; 1024];
-        let n = stdin.read(&mut buf).await?;
-        info!("Read {} bytes from stdin", n);
-        buf.truncate(n);
-        sender.send(Message::binary(buf)).await?;
-        info!("Sent message to Channel")
+    while let Some(line) = lines.next_line().await? {
+        let mut words = line.split_whitespace();
+        let Some(method) = words.next() else {
+            continue;
+        };
+        let request = method.parse::<Method>().and_then(|method| {
+            let params = words
+                .map(str::parse)
+                .collect::<Result<Vec<StreamName>, _>>()?;
+            Ok((method, params))
+        });
+        match request {
+            Ok((method, params)) => {
+                let id = client.request(method, params)?;
+                info!("Sent request {}", id);
+            }
+            Err(e) => eprintln!("Invalid request: {}", e),
+        }
     }
+    Ok(())
 }
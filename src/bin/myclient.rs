@@ -1,10 +1,8 @@
-use futures_util::{Sink, SinkExt, Stream, StreamExt};
-use tokio::io::{stdin, stdout, AsyncReadExt, AsyncWriteExt};
+use backpack::subscrib_stream::*;
+use backpack::{BackpackClient, ClientEvent, ClientEvents, MarketRegistry};
+use futures_util::StreamExt;
+use tokio::io::{stdin, stdout, AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::join;
-use tokio::sync::mpsc::{channel, Receiver, Sender};
-use tokio_stream::wrappers::ReceiverStream;
-use tokio_tungstenite::tungstenite::Error;
-use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
 use tracing::info;
 
 #[tokio::main]
@@ -13,47 +11,60 @@ async fn main() -> anyhow::Result<()> {
     let url = std::env::args()
         .nth(1)
         .unwrap_or_else(|| panic!("Please provide a URL"));
-    let (tx, rx) = channel(32);
-    let read_stdin = tokio::spawn(read_stdin(tx));
-    let (ws_stream, _) = connect_async(url).await?;
-    let (write, read) = ws_stream.split();
-    let ws_to_stdout_handle = tokio::spawn(ws_to_stdout(read));
-    let stdin_to_ws_handle = tokio::spawn(stdin_to_ws(rx, write));
-    let _ = join!(ws_to_stdout_handle, stdin_to_ws_handle, read_stdin);
-    Ok(())
-}
-
-async fn read_stdin(sender: Sender<Message>) -> anyhow::Result<()> {
-    let mut stdin = stdin();
-    loop {
-        let mut buf = vec![0; 1024];
-        let n = stdin.read(&mut buf).await?;
-        info!("Read {} bytes from stdin", n);
-        buf.truncate(n);
-        sender.send(Message::binary(buf)).await?;
+    // An optional JSON array of markets to accept stream names for.
+    if let Some(path) = std::env::args().nth(2) {
+        MarketRegistry::from_file(&path)?.install();
+        info!("Loaded markets from: {}", path);
     }
+    let (client, events) = BackpackClient::connect(&url).await?;
+    let read_stdin = tokio::spawn(read_stdin(client));
+    let events_to_stdout_handle = tokio::spawn(events_to_stdout(events));
+    let _ = join!(events_to_stdout_handle, read_stdin);
+    Ok(())
 }
 
-async fn stdin_to_ws(
-    rx: Receiver<Message>,
-    mut write: impl Sink<Message> + Unpin,
-) -> anyhow::Result<()> {
-    let mut stream = ReceiverStream::from(rx);
-    while let Some(msg) = stream.next().await {
-        if (write.send(msg).await).is_err() {
-            eprintln!("Error!");
-            break;
+// Each line is a request such as `SUBSCRIBE depth.SOL_USDC trade.SOL_USDC`.
+async fn read_stdin(client: BackpackClient) -> anyhow::Result<()> {
+    let mut lines = BufReader::new(stdin()).lines();
+    while let Some(line) = lines.next_line().await? {
+        let mut words = line.split_whitespace();
+        let Some(method) = words.next() else {
+            continue;
+        };
+        let request = method.parse::<Method>().and_then(|method| {
+            let params = words
+                .map(str::parse)
+                .collect::<Result<Vec<StreamName>, _>>()?;
+            Ok((method, params))
+        });
+        match request {
+            Ok((method, params)) => {
+                let id = client.request(method, params)?;
+                info!("Sent request {}", id);
+            }
+            Err(e) => eprintln!("Invalid request: {}", e),
         }
     }
     Ok(())
 }
 
-async fn ws_to_stdout(
-    mut read: impl Stream<Item = Result<Message, Error>> + Unpin,
-) -> anyhow::Result<()> {
-    while let Some(msg) = read.next().await {
-        let data = msg.unwrap().into_data();
-        stdout().write_all(&data).await?;
+// One line per event with its type, symbol and event time.
+async fn events_to_stdout(mut events: ClientEvents) -> anyhow::Result<()> {
+    while let Some(event) = events.next().await {
+        let line = match event {
+            ClientEvent::Event { envelope, .. } => {
+                let event = envelope.data;
+                format!(
+                    "{} {} {} {}\n",
+                    envelope.stream,
+                    event.event_type(),
+                    event.symbol(),
+                    event.event_time()
+                )
+            }
+            other => format!("{:?}\n", other),
+        };
+        stdout().write_all(line.as_bytes()).await?;
     }
     Ok(())
 }
@@ -0,0 +1,640 @@
+use crate::clock::{Clock, SystemClock};
+use crate::envelope::{Frame, StreamEnvelope};
+use crate::error::ClientError;
+use crate::event_type::Event;
+use crate::heartbeat::{Heartbeat, HeartbeatAction, HeartbeatConfig};
+use crate::response::{ErrorCode, Response};
+use crate::subscrib_stream::{Method, StreamName, SubscribStream};
+use futures::{SinkExt, Stream, StreamExt};
+use rand::Rng;
+use std::collections::BTreeMap;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::time::sleep;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+use tracing::{info, warn};
+
+const EVENT_BUFFER: usize = 1000;
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+#[derive(Debug, Clone)]
+pub enum ClientEvent {
+    // `received_at` is the local clock in Unix microseconds when the frame came in.
+    Event {
+        envelope: StreamEnvelope<Event>,
+        received_at: u64,
+    },
+    Response(Response),
+    // The connection dropped. The client is reconnecting unless the stream ends here.
+    Disconnected {
+        reason: String,
+    },
+    Reconnected {
+        attempts: u32,
+    },
+    // Updates for these streams may have been missed while disconnected, so anything built
+    // from them, such as an order book, has to be resynced.
+    Gap {
+        streams: Vec<StreamName>,
+    },
+    // Round-trip time of the client's last answered ping.
+    Latency {
+        round_trip: Duration,
+    },
+}
+
+// Exponential backoff between reconnect attempts. Each delay is spread by up to `jitter`
+// either way, so clients dropped together do not all come back at the same moment.
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    pub multiplier: f64,
+    pub jitter: f64,
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: 0.2,
+            max_attempts: None,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    pub fn never() -> Self {
+        Self {
+            max_attempts: Some(0),
+            ..Self::default()
+        }
+    }
+
+    // The delay before the given attempt, counting from 1.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let backoff = self.initial_delay.as_secs_f64() * self.multiplier.powi(exponent);
+        let backoff = Duration::try_from_secs_f64(backoff)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+        let jitter = rand::thread_rng().gen_range(-self.jitter..=self.jitter);
+        backoff.mul_f64((1.0 + jitter).max(0.0))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ClientConfig {
+    pub reconnect: ReconnectPolicy,
+    pub heartbeat: HeartbeatConfig,
+}
+
+// Handle to a websocket connection run by a background task. Requests go out through the
+// handle and everything the server sends comes back on the paired `ClientEvents` stream.
+// A dropped connection is re-established and its subscriptions restored. The connection
+// is closed once the event stream is dropped.
+pub struct BackpackClient {
+    commands: mpsc::UnboundedSender<SubscribStream>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl BackpackClient {
+    pub async fn connect(url: &str) -> Result<(Self, ClientEvents), ClientError> {
+        Self::connect_with(url, ClientConfig::default()).await
+    }
+
+    pub async fn connect_with(
+        url: &str,
+        config: ClientConfig,
+    ) -> Result<(Self, ClientEvents), ClientError> {
+        let (ws_stream, _) = connect_async(url).await?;
+        info!("Connected to: {}", url);
+        let (commands, command_rx) = mpsc::unbounded_channel();
+        let (event_tx, events) = mpsc::channel(EVENT_BUFFER);
+        let next_id = Arc::new(AtomicU64::new(1));
+        let session = Session {
+            url: url.to_string(),
+            config,
+            commands: command_rx,
+            events: event_tx,
+            handle_dropped: false,
+            subscriptions: BTreeMap::new(),
+            pending: BTreeMap::new(),
+            restore: None,
+            next_id: next_id.clone(),
+        };
+        tokio::spawn(session.run(ws_stream));
+        let client = Self { commands, next_id };
+        Ok((client, ClientEvents { events }))
+    }
+
+    // Each request gets its own id, which comes back on the `Response` to it.
+    pub fn subscribe(&self, params: Vec<StreamName>) -> Result<u64, ClientError> {
+        self.request(Method::Subscribe, params)
+    }
+
+    pub fn unsubscribe(&self, params: Vec<StreamName>) -> Result<u64, ClientError> {
+        self.request(Method::Unsubscribe, params)
+    }
+
+    pub fn request(&self, method: Method, params: Vec<StreamName>) -> Result<u64, ClientError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let request = SubscribStream {
+            id: Some(id),
+            method,
+            params,
+        };
+        self.commands
+            .send(request)
+            .map_err(|_| ClientError::Closed)?;
+        Ok(id)
+    }
+}
+
+pub struct ClientEvents {
+    events: mpsc::Receiver<ClientEvent>,
+}
+
+impl Stream for ClientEvents {
+    type Item = ClientEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.events.poll_recv(cx)
+    }
+}
+
+enum Disconnect {
+    // The caller dropped the event stream, so there is nobody left to reconnect for.
+    Stopped,
+    Lost(String),
+}
+
+struct Session {
+    url: String,
+    config: ClientConfig,
+    commands: mpsc::UnboundedReceiver<SubscribStream>,
+    events: mpsc::Sender<ClientEvent>,
+    handle_dropped: bool,
+    // Streams to restore after a reconnect, keyed by name. Requests update this when sent
+    // and the streams a rejected SUBSCRIBE added are rolled back when its error comes in.
+    subscriptions: BTreeMap<String, StreamName>,
+    pending: BTreeMap<u64, PendingRequest>,
+    // Id of the SUBSCRIBE that restores the streams after a reconnect, shared with the
+    // handle's ids so a response to it cannot be mistaken for one to the caller's request.
+    restore: Option<u64>,
+    next_id: Arc<AtomicU64>,
+}
+
+struct PendingRequest {
+    request: SubscribStream,
+    // Streams of a SUBSCRIBE that were not subscribed before it was sent.
+    added: Vec<StreamName>,
+}
+
+impl Session {
+    async fn run(mut self, mut ws_stream: WsStream) {
+        loop {
+            let reason = match self.connection(ws_stream).await {
+                Disconnect::Stopped => return,
+                Disconnect::Lost(reason) => reason,
+            };
+            warn!("Connection to {} lost: {}", self.url, reason);
+            self.pending.clear();
+            self.restore = None;
+            if !self.emit(ClientEvent::Disconnected { reason }).await {
+                return;
+            }
+            let Some((reconnected, attempts)) = self.reconnect().await else {
+                warn!("Giving up on reconnecting to {}", self.url);
+                return;
+            };
+            ws_stream = reconnected;
+            if !self.emit(ClientEvent::Reconnected { attempts }).await {
+                return;
+            }
+            let Some(request) = self.restore_request() else {
+                continue;
+            };
+            let text = serde_json::to_string(&request).unwrap();
+            if let Err(e) = ws_stream.send(Message::Text(text)).await {
+                warn!("Failed to restore subscriptions: {}", e);
+            }
+        }
+    }
+
+    // The gap is only reported once the server has accepted the streams again.
+    fn restore_request(&mut self) -> Option<SubscribStream> {
+        if self.subscriptions.is_empty() {
+            return None;
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let request = SubscribStream {
+            id: Some(id),
+            method: Method::Subscribe,
+            params: self.subscriptions.values().cloned().collect(),
+        };
+        // Every stream is new to the fresh connection, so all of them go if it is rejected.
+        let pending = PendingRequest {
+            request: request.clone(),
+            added: request.params.clone(),
+        };
+        self.pending.insert(id, pending);
+        self.restore = Some(id);
+        Some(request)
+    }
+
+    async fn reconnect(&self) -> Option<(WsStream, u32)> {
+        let mut attempt = 0;
+        let policy = &self.config.reconnect;
+        while policy.max_attempts.is_none_or(|max| attempt < max) {
+            attempt += 1;
+            let delay = policy.delay(attempt);
+            info!(
+                "Reconnecting to {} in {:?} (attempt {})",
+                self.url, delay, attempt
+            );
+            sleep(delay).await;
+            match connect_async(self.url.as_str()).await {
+                Ok((ws_stream, _)) => return Some((ws_stream, attempt)),
+                Err(e) => warn!("Reconnect attempt {} failed: {}", attempt, e),
+            }
+        }
+        None
+    }
+
+    async fn emit(&self, event: ClientEvent) -> bool {
+        self.events.send(event).await.is_ok()
+    }
+
+    fn track(&mut self, request: &SubscribStream) {
+        let mut added = Vec::new();
+        for param in &request.params {
+            let name = param.to_string();
+            match request.method {
+                Method::Subscribe => {
+                    if self.subscriptions.insert(name, param.clone()).is_none() {
+                        added.push(param.clone());
+                    }
+                }
+                Method::Unsubscribe => {
+                    self.subscriptions.remove(&name);
+                }
+            }
+        }
+        if let Some(id) = request.id {
+            let pending = PendingRequest {
+                request: request.clone(),
+                added,
+            };
+            self.pending.insert(id, pending);
+        }
+    }
+
+    // Returns the streams to report a gap for when this accepts the restoring SUBSCRIBE.
+    fn acknowledge(&mut self, response: &Response) -> Option<Vec<StreamName>> {
+        let id = response.id?;
+        let PendingRequest { request, added } = self.pending.remove(&id)?;
+        let restored = self.restore == Some(id);
+        if restored {
+            self.restore = None;
+        }
+        let Some(error) = &response.error else {
+            return restored.then_some(request.params);
+        };
+        // The server is already streaming whatever it says is subscribed, so nothing is
+        // rolled back for that. Otherwise only the streams this request added are dropped,
+        // those that were live before it are still streaming.
+        if error.code != ErrorCode::AlreadySubscribed {
+            for param in &added {
+                self.subscriptions.remove(&param.to_string());
+            }
+        }
+        None
+    }
+
+    async fn connection(&mut self, ws_stream: WsStream) -> Disconnect {
+        let (mut write, mut read) = ws_stream.split();
+        let mut heartbeat = Heartbeat::new(self.config.heartbeat);
+        let disconnect = loop {
+            tokio::select! {
+                action = heartbeat.tick() => {
+                    let ping = match action {
+                        HeartbeatAction::Ping(ping) => ping,
+                        HeartbeatAction::TimedOut => {
+                            let _ = write.send(Heartbeat::close_frame()).await;
+                            break Disconnect::Lost("heartbeat timed out".to_string());
+                        }
+                    };
+                    if let Err(e) = write.send(ping).await {
+                        break Disconnect::Lost(e.to_string());
+                    }
+                }
+                command = self.commands.recv(), if !self.handle_dropped => {
+                    // Without a handle no more requests can come, but events still flow.
+                    let Some(request) = command else {
+                        self.handle_dropped = true;
+                        continue;
+                    };
+                    self.track(&request);
+                    let text = serde_json::to_string(&request).unwrap();
+                    if let Err(e) = write.send(Message::Text(text)).await {
+                        break Disconnect::Lost(e.to_string());
+                    }
+                }
+                message = read.next() => {
+                    let received_at = SystemClock.now_micros();
+                    let event = match message {
+                        Some(Ok(Message::Text(text))) => match Frame::<Event>::parse(&text) {
+                            Ok(Frame::Event(envelope)) => ClientEvent::Event {
+                                envelope,
+                                received_at,
+                            },
+                            Ok(Frame::Response(response)) => {
+                                let gap = self.acknowledge(&response);
+                                if !self.emit(ClientEvent::Response(response)).await {
+                                    break Disconnect::Stopped;
+                                }
+                                let Some(streams) = gap else {
+                                    continue;
+                                };
+                                ClientEvent::Gap { streams }
+                            }
+                            Err(_) => {
+                                warn!("Received an unrecognised frame: {}", text);
+                                continue;
+                            }
+                        },
+                        Some(Ok(message @ Message::Pong(_))) => {
+                            match heartbeat.on_message(&message) {
+                                Some(round_trip) => ClientEvent::Latency { round_trip },
+                                None => continue,
+                            }
+                        }
+                        Some(Ok(Message::Close(frame))) => {
+                            break Disconnect::Lost(format!("closed by the server: {:?}", frame));
+                        }
+                        Some(Ok(_)) => continue,
+                        Some(Err(e)) => break Disconnect::Lost(e.to_string()),
+                        None => break Disconnect::Lost("connection closed".to_string()),
+                    };
+                    if !self.emit(event).await {
+                        break Disconnect::Stopped;
+                    }
+                }
+            }
+        };
+        let _ = write.close().await;
+        disconnect
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::accept_async;
+
+    async fn listen() -> (TcpListener, String) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        (listener, url)
+    }
+
+    async fn accept(listener: &TcpListener) -> WebSocketStream<TcpStream> {
+        let (stream, _) = listener.accept().await.unwrap();
+        accept_async(stream).await.unwrap()
+    }
+
+    async fn next_request(ws: &mut WebSocketStream<TcpStream>) -> SubscribStream {
+        let message = ws.next().await.unwrap().unwrap();
+        SubscribStream::from_request(message.to_text().unwrap()).unwrap()
+    }
+
+    async fn reply(ws: &mut WebSocketStream<TcpStream>, response: &Response) {
+        let text = serde_json::to_string(response).unwrap();
+        ws.send(Message::Text(text)).await.unwrap();
+    }
+
+    async fn next_event(events: &mut ClientEvents) -> ClientEvent {
+        tokio::time::timeout(Duration::from_secs(5), events.next())
+            .await
+            .expect("no client event in time")
+            .expect("the client stopped")
+    }
+
+    #[tokio::test]
+    async fn responses_and_events_come_back_decoded() {
+        let (listener, url) = listen().await;
+        let server = tokio::spawn(async move {
+            let mut ws = accept(&listener).await;
+            let request = next_request(&mut ws).await;
+            reply(&mut ws, &Response::success(request.id, request.params)).await;
+            let event = r#"{"stream":"trade.SOL_USDC","data":{"e":"trade","E":1,"s":"SOL_USDC","p":"18.68","q":"0.122","b":"1","a":"2","t":3,"T":1,"m":true}}"#;
+            ws.send(Message::Text(event.to_string())).await.unwrap();
+            ws
+        });
+        let config = ClientConfig {
+            reconnect: ReconnectPolicy::never(),
+            ..ClientConfig::default()
+        };
+        let (client, mut events) = BackpackClient::connect_with(&url, config).await.unwrap();
+        let id = client
+            .subscribe(vec!["trade.SOL_USDC".parse().unwrap()])
+            .unwrap();
+
+        let ClientEvent::Response(response) = next_event(&mut events).await else {
+            panic!("expected the response first");
+        };
+        assert_eq!(response.id, Some(id));
+        assert_eq!(response.result, Some(vec!["trade.SOL_USDC".to_string()]));
+        let ClientEvent::Event {
+            envelope,
+            received_at,
+        } = next_event(&mut events).await
+        else {
+            panic!("expected the trade");
+        };
+        assert_eq!(envelope.stream, "trade.SOL_USDC");
+        assert!(matches!(envelope.data, Event::Trade(_)));
+        assert!(received_at > 0);
+        drop(server.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn request_ids_carry_on_across_a_reconnect() {
+        let (listener, url) = listen().await;
+        let server = tokio::spawn(async move {
+            let mut ws = accept(&listener).await;
+            let depth = next_request(&mut ws).await;
+            reply(&mut ws, &Response::success(depth.id, depth.params)).await;
+            let trade = next_request(&mut ws).await;
+            let rejected = Response::error(trade.id, ErrorCode::InvalidSymbol, "unknown market");
+            reply(&mut ws, &rejected).await;
+            ws.close(None).await.unwrap();
+
+            let mut ws = accept(&listener).await;
+            let restore = next_request(&mut ws).await;
+            reply(
+                &mut ws,
+                &Response::success(restore.id, restore.params.clone()),
+            )
+            .await;
+            (restore, ws)
+        });
+        let config = ClientConfig {
+            reconnect: ReconnectPolicy {
+                initial_delay: Duration::from_millis(10),
+                max_delay: Duration::from_millis(10),
+                ..ReconnectPolicy::default()
+            },
+            ..ClientConfig::default()
+        };
+        let (client, mut events) = BackpackClient::connect_with(&url, config).await.unwrap();
+        assert_eq!(
+            client
+                .subscribe(vec!["depth.SOL_USDC".parse().unwrap()])
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            client
+                .subscribe(vec!["trade.SOL_USDC".parse().unwrap()])
+                .unwrap(),
+            2
+        );
+
+        let mut seen = Vec::new();
+        loop {
+            match next_event(&mut events).await {
+                ClientEvent::Response(response) => seen.push((response.id, response.is_success())),
+                ClientEvent::Gap { streams } => {
+                    assert_eq!(streams, vec!["depth.SOL_USDC".parse().unwrap()]);
+                    break;
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(seen, [(Some(1), true), (Some(2), false), (Some(3), true)]);
+        let (restore, _ws) = server.await.unwrap();
+        assert_eq!(restore.id, Some(3));
+        assert_eq!(restore.params.len(), 1);
+        // Ids handed out afterwards do not collide with the restoring request.
+        assert_eq!(
+            client
+                .subscribe(vec!["trade.SOL_USDC".parse().unwrap()])
+                .unwrap(),
+            4
+        );
+    }
+
+    fn session() -> Session {
+        let (_, commands) = mpsc::unbounded_channel();
+        let (events, _) = mpsc::channel(1);
+        Session {
+            url: "ws://127.0.0.1:8080".to_string(),
+            config: ClientConfig::default(),
+            commands,
+            events,
+            handle_dropped: false,
+            subscriptions: BTreeMap::new(),
+            pending: BTreeMap::new(),
+            restore: None,
+            next_id: Arc::new(AtomicU64::new(1)),
+        }
+    }
+
+    fn subscribe(session: &mut Session, id: u64, params: &[&str]) {
+        session.track(&SubscribStream {
+            id: Some(id),
+            method: Method::Subscribe,
+            params: params.iter().map(|param| param.parse().unwrap()).collect(),
+        });
+    }
+
+    #[test]
+    fn accepted_restore_reports_a_gap() {
+        let mut session = session();
+        subscribe(&mut session, 100, &["depth.SOL_USDC"]);
+        session.pending.clear();
+
+        let request = session.restore_request().unwrap();
+        assert_eq!(request.id, Some(1));
+        let streams = request.params.clone();
+        assert_eq!(
+            session.acknowledge(&Response::success(request.id, streams.clone())),
+            Some(streams)
+        );
+        assert!(session.pending.is_empty());
+        assert_eq!(session.restore, None);
+    }
+
+    #[test]
+    fn rejected_restore_is_not_requested_again() {
+        let mut session = session();
+        subscribe(&mut session, 100, &["depth.SOL_USDC"]);
+        session.pending.clear();
+
+        let request = session.restore_request().unwrap();
+        let rejected = Response::error(request.id, ErrorCode::InvalidSymbol, "unknown market");
+        assert_eq!(session.acknowledge(&rejected), None);
+        assert!(session.subscriptions.is_empty());
+        assert!(session.restore_request().is_none());
+    }
+
+    fn restored(session: &mut Session) -> Vec<String> {
+        session.pending.clear();
+        let request = session.restore_request().unwrap();
+        request.params.iter().map(StreamName::to_string).collect()
+    }
+
+    #[test]
+    fn rejected_subscribe_keeps_streams_that_were_already_live() {
+        let mut session = session();
+        subscribe(&mut session, 1, &["depth.SOL_USDC"]);
+        let live = vec!["depth.SOL_USDC".parse().unwrap()];
+        session.acknowledge(&Response::success(Some(1), live));
+
+        subscribe(&mut session, 2, &["depth.SOL_USDC", "trade.SOL_USDC"]);
+        let rejected = Response::error(Some(2), ErrorCode::TooManySubscriptions, "at most 1");
+        session.acknowledge(&rejected);
+        assert_eq!(restored(&mut session), ["depth.SOL_USDC"]);
+    }
+
+    #[test]
+    fn already_subscribed_is_not_rolled_back() {
+        let mut session = session();
+        subscribe(&mut session, 1, &["depth.SOL_USDC"]);
+        session.acknowledge(&Response::success(
+            Some(1),
+            vec!["depth.SOL_USDC".parse().unwrap()],
+        ));
+
+        subscribe(&mut session, 2, &["depth.SOL_USDC", "trade.SOL_USDC"]);
+        let message = "already subscribed to depth.SOL_USDC";
+        session.acknowledge(&Response::error(
+            Some(2),
+            ErrorCode::AlreadySubscribed,
+            message,
+        ));
+        assert_eq!(restored(&mut session), ["depth.SOL_USDC", "trade.SOL_USDC"]);
+    }
+
+    #[test]
+    fn response_to_a_caller_request_is_not_a_gap() {
+        let mut session = session();
+        subscribe(&mut session, 7, &["trade.SOL_USDC"]);
+        let response = Response::success(Some(7), vec!["trade.SOL_USDC".parse().unwrap()]);
+        assert_eq!(session.acknowledge(&response), None);
+        assert!(session.subscriptions.contains_key("trade.SOL_USDC"));
+    }
+}
@@ -0,0 +1,105 @@
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Source of event timestamps, in Unix microseconds like the `E` and `T` fields.
+pub trait Clock: Send + Sync {
+    fn now_micros(&self) -> u64;
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_micros(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_micros() as u64)
+    }
+}
+
+// A clock starting at `start` that runs `speed` times faster than real time and can be
+// moved by hand. With a speed of zero it only moves when stepped, which suits tests.
+#[derive(Debug)]
+pub struct SimulatedClock {
+    start: u64,
+    speed: f64,
+    origin: Instant,
+    offset: AtomicI64,
+}
+
+impl SimulatedClock {
+    pub fn manual(start: u64) -> Self {
+        Self::scaled(start, 0.0)
+    }
+
+    pub fn scaled(start: u64, speed: f64) -> Self {
+        Self {
+            start,
+            speed,
+            origin: Instant::now(),
+            offset: AtomicI64::new(0),
+        }
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.offset
+            .fetch_add(duration.as_micros() as i64, Ordering::SeqCst);
+    }
+
+    pub fn set(&self, now: u64) {
+        let drift = now as i64 - self.now_micros() as i64;
+        self.offset.fetch_add(drift, Ordering::SeqCst);
+    }
+
+    fn scaled_elapsed(&self) -> u64 {
+        (self.origin.elapsed().as_micros() as f64 * self.speed) as u64
+    }
+}
+
+impl Clock for SimulatedClock {
+    fn now_micros(&self) -> u64 {
+        let now = (self.start + self.scaled_elapsed()) as i64 + self.offset.load(Ordering::SeqCst);
+        now.max(0) as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manual_clock_only_moves_when_stepped() {
+        let clock = SimulatedClock::manual(1_000_000);
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(clock.now_micros(), 1_000_000);
+        clock.advance(Duration::from_millis(250));
+        assert_eq!(clock.now_micros(), 1_250_000);
+    }
+
+    #[test]
+    fn scaled_clock_runs_faster_than_real_time() {
+        let started = Instant::now();
+        let clock = SimulatedClock::scaled(1_000_000, 1000.0);
+        std::thread::sleep(Duration::from_millis(10));
+        let elapsed = clock.now_micros() - 1_000_000;
+        // At least the 10ms slept, times the speed, and no more than the real time passed.
+        assert!(elapsed >= 10_000_000);
+        assert!(elapsed <= started.elapsed().as_micros() as u64 * 1000);
+        assert_eq!(clock.speed(), 1000.0);
+    }
+
+    #[test]
+    fn set_moves_the_clock_either_way() {
+        let clock = SimulatedClock::manual(1_000_000);
+        clock.set(5_000_000);
+        assert_eq!(clock.now_micros(), 5_000_000);
+        clock.set(2_000_000);
+        assert_eq!(clock.now_micros(), 2_000_000);
+        clock.advance(Duration::from_micros(1));
+        assert_eq!(clock.now_micros(), 2_000_001);
+    }
+}
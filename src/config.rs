@@ -0,0 +1,231 @@
+use crate::error::ConfigError;
+use crate::heartbeat::HeartbeatConfig;
+use crate::market::{Market, MarketRegistry};
+use crate::replay::ReplaySettings;
+use crate::simulator::SimulationConfig;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct HeartbeatSettings {
+    pub ping_interval_secs: u64,
+    pub pong_timeout_secs: u64,
+}
+
+impl Default for HeartbeatSettings {
+    fn default() -> Self {
+        Self {
+            ping_interval_secs: 10,
+            pong_timeout_secs: 20,
+        }
+    }
+}
+
+impl From<&HeartbeatSettings> for HeartbeatConfig {
+    fn from(settings: &HeartbeatSettings) -> Self {
+        Self {
+            interval: Duration::from_secs(settings.ping_interval_secs),
+            timeout: Duration::from_secs(settings.pong_timeout_secs),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct ConnectionLimits {
+    pub max_connections: Option<usize>,
+    // Streams a single connection may be subscribed to at once.
+    pub max_subscriptions: Option<usize>,
+    // Messages a stream buffers for a subscriber that falls behind before it skips ahead.
+    pub stream_capacity: usize,
+    // Messages queued for a connection before its streams wait on the socket.
+    pub send_buffer: usize,
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> Self {
+        Self {
+            max_connections: None,
+            max_subscriptions: None,
+            stream_capacity: 1000,
+            send_buffer: 1000,
+        }
+    }
+}
+
+// Everything `backpack_server` can be set up with, read from TOML or JSON, e.g.
+// listen = "127.0.0.1:8080"
+// tickMillis = 1000
+// [heartbeat]
+// pingIntervalSecs = 10
+// [limits]
+// maxConnections = 100
+// [simulation.SOL_USDC]
+// initialPrice = 165.0
+// Markets default to the built-in registry when none are listed. With a `[replay]` section
+// streams are played from recordings rather than simulated.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub listen: String,
+    pub http_listen: Option<String>,
+    pub tick_millis: u64,
+    pub heartbeat: HeartbeatSettings,
+    pub limits: ConnectionLimits,
+    pub markets: Option<Vec<Market>>,
+    pub simulation: SimulationConfig,
+    pub replay: Option<ReplaySettings>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            listen: "127.0.0.1:8080".to_string(),
+            http_listen: None,
+            tick_millis: 1000,
+            heartbeat: HeartbeatSettings::default(),
+            limits: ConnectionLimits::default(),
+            markets: None,
+            simulation: SimulationConfig::default(),
+            replay: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    // `.toml` files are read as TOML, anything else as JSON.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("toml") => toml::from_str(&content).map_err(|source| ConfigError::Toml {
+                path: path.to_path_buf(),
+                source,
+            }),
+            _ => serde_json::from_str(&content).map_err(|source| ConfigError::Json {
+                path: path.to_path_buf(),
+                source,
+            }),
+        }
+    }
+
+    pub fn registry(&self) -> MarketRegistry {
+        match &self.markets {
+            Some(markets) => markets
+                .iter()
+                .cloned()
+                .fold(MarketRegistry::new(), MarketRegistry::with_market),
+            None => MarketRegistry::with_defaults(),
+        }
+    }
+
+    pub fn tick(&self) -> Duration {
+        Duration::from_millis(self.tick_millis)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        check_address("listen", &self.listen)?;
+        if let Some(http_listen) = &self.http_listen {
+            check_address("httpListen", http_listen)?;
+        }
+        let non_zero = [
+            ("tickMillis", self.tick_millis),
+            (
+                "heartbeat.pingIntervalSecs",
+                self.heartbeat.ping_interval_secs,
+            ),
+            (
+                "heartbeat.pongTimeoutSecs",
+                self.heartbeat.pong_timeout_secs,
+            ),
+            ("limits.streamCapacity", self.limits.stream_capacity as u64),
+            ("limits.sendBuffer", self.limits.send_buffer as u64),
+        ];
+        if let Some((field, _)) = non_zero.iter().find(|(_, value)| *value == 0) {
+            return Err(ConfigError::Zero(field));
+        }
+        if self.limits.max_connections == Some(0) {
+            return Err(ConfigError::Zero("limits.maxConnections"));
+        }
+        if self.limits.max_subscriptions == Some(0) {
+            return Err(ConfigError::Zero("limits.maxSubscriptions"));
+        }
+        let registry = self.registry();
+        if registry.markets().next().is_none() {
+            return Err(ConfigError::NoMarkets);
+        }
+        for market in registry.markets() {
+            check_market(market)?;
+        }
+        for (symbol, settings) in &self.simulation.markets {
+            if registry.get(symbol.as_str()).is_none() {
+                return Err(ConfigError::UnknownMarket(symbol.clone()));
+            }
+            let invalid = |reason| ConfigError::InvalidSimulation {
+                symbol: symbol.clone(),
+                reason,
+            };
+            if !(settings.initial_price.is_finite() && settings.initial_price > 0.0) {
+                return Err(invalid("initial price must be positive"));
+            }
+            settings.price_model.validate().map_err(invalid)?;
+        }
+        if let Some(replay) = &self.replay {
+            check_replay(replay, self.http_listen.is_some())?;
+        }
+        Ok(())
+    }
+}
+
+fn check_replay(replay: &ReplaySettings, serves_http: bool) -> Result<(), ConfigError> {
+    if replay.files.is_empty() {
+        return Err(ConfigError::InvalidReplay("no files to replay"));
+    }
+    if !(replay.speed.is_finite() && replay.speed > 0.0) {
+        return Err(ConfigError::InvalidReplay("speed must be positive"));
+    }
+    // Replayed markets have no simulated book to take a snapshot of.
+    if serves_http {
+        return Err(ConfigError::InvalidReplay(
+            "depth snapshots are not served while replaying",
+        ));
+    }
+    for path in &replay.files {
+        std::fs::metadata(path).map_err(|source| ConfigError::Read {
+            path: path.clone(),
+            source,
+        })?;
+    }
+    Ok(())
+}
+
+fn check_address(field: &'static str, value: &str) -> Result<(), ConfigError> {
+    value
+        .parse::<SocketAddr>()
+        .map(|_| ())
+        .map_err(|_| ConfigError::InvalidAddress {
+            field,
+            value: value.to_string(),
+        })
+}
+
+fn check_market(market: &Market) -> Result<(), ConfigError> {
+    let invalid = |reason| ConfigError::InvalidMarket {
+        symbol: market.symbol.clone(),
+        reason,
+    };
+    if market.tick_size <= Decimal::ZERO {
+        return Err(invalid("tick size must be positive"));
+    }
+    if market.step_size <= Decimal::ZERO {
+        return Err(invalid("step size must be positive"));
+    }
+    Ok(())
+}
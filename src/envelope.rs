@@ -0,0 +1,78 @@
+use crate::response::Response;
+use serde::de::{self, DeserializeOwned};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use tokio_tungstenite::tungstenite::Message;
+
+// Combined-stream wrapper around every event, e.g. `{"stream": "depth.SOL_USDC", "data": {...}}`.
+// The stream name is kept as sent so frames for markets missing from the registry still decode.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StreamEnvelope<T = Value> {
+    pub stream: String,
+    pub data: T,
+}
+
+impl<T> StreamEnvelope<T> {
+    pub fn new(stream: impl Into<String>, data: T) -> Self {
+        Self {
+            stream: stream.into(),
+            data,
+        }
+    }
+}
+
+impl<T: Serialize> StreamEnvelope<T> {
+    pub fn to_message(&self) -> Message {
+        Message::Text(serde_json::to_string(self).unwrap())
+    }
+}
+
+// Any text frame the server sends: a stream event or the reply to a request.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum Frame<T = Value> {
+    Event(StreamEnvelope<T>),
+    Response(Response),
+}
+
+// Told apart by shape, so an event that fails to decode is an error rather than a response
+// with nothing in it.
+impl<'de, T: DeserializeOwned> Deserialize<'de> for Frame<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        let frame = match value.get("stream") {
+            Some(_) => serde_json::from_value(value).map(Frame::Event),
+            None => serde_json::from_value(value).map(Frame::Response),
+        };
+        frame.map_err(de::Error::custom)
+    }
+}
+
+impl<T: DeserializeOwned> Frame<T> {
+    pub fn parse(text: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_type::Event;
+
+    #[test]
+    fn frames_are_told_apart_by_shape() {
+        let event = r#"{"stream":"trade.SOL_USDC","data":{"e":"trade","E":1,"s":"SOL_USDC","p":"18.68","q":"0.122","b":"1","a":"2","t":3,"T":1,"m":true}}"#;
+        assert!(matches!(Frame::<Event>::parse(event), Ok(Frame::Event(_))));
+        let response = r#"{"id":1,"result":["depth.SOL_USDC"]}"#;
+        assert!(matches!(
+            Frame::<Event>::parse(response),
+            Ok(Frame::Response(Response { id: Some(1), .. }))
+        ));
+    }
+
+    #[test]
+    fn undecodable_event_is_not_a_response() {
+        let truncated = r#"{"stream":"trade.SOL_USDC","data":{"e":"trade","E":1,"s":"SOL_USDC"}}"#;
+        assert!(Frame::<Event>::parse(truncated).is_err());
+    }
+}
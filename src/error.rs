@@ -0,0 +1,109 @@
+use crate::subscrib_stream::Symbol;
+use rust_decimal::Decimal;
+use std::path::PathBuf;
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    #[error("unknown event type: {0}")]
+    UnknownEventType(String),
+    #[error("unknown symbol: {0}")]
+    UnknownSymbol(String),
+    #[error("unknown kline interval: {0}")]
+    UnknownInterval(String),
+    #[error("unknown method: {0}")]
+    UnknownMethod(String),
+    #[error("missing symbol in stream name: {0}")]
+    MissingSymbol(String),
+    #[error("missing interval in stream name: {0}")]
+    MissingInterval(String),
+    #[error("extra segments in stream name: {0}")]
+    ExtraSegments(String),
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum HubError {
+    #[error(transparent)]
+    Parse(#[from] ParseError),
+    #[error("the replay has finished")]
+    ReplayFinished,
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum BookError {
+    #[error("missed depth updates {expected} to {}", first - 1)]
+    Gap { expected: u64, first: u64 },
+    #[error("crossed book: best bid {bid} at or above best ask {ask}")]
+    Crossed { bid: Decimal, ask: Decimal },
+}
+
+#[derive(Error, Debug)]
+pub enum ClientError {
+    #[error("websocket error: {0}")]
+    WebSocket(Box<tokio_tungstenite::tungstenite::Error>),
+    #[error("the client connection has shut down")]
+    Closed,
+}
+
+impl From<tokio_tungstenite::tungstenite::Error> for ClientError {
+    fn from(error: tokio_tungstenite::tungstenite::Error) -> Self {
+        ClientError::WebSocket(Box::new(error))
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("failed to read {}: {source}", .path.display())]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("invalid TOML in {}: {source}", .path.display())]
+    Toml {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    #[error("invalid JSON in {}: {source}", .path.display())]
+    Json {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    #[error("{field} is not a socket address: {value}")]
+    InvalidAddress { field: &'static str, value: String },
+    #[error("{0} must be greater than zero")]
+    Zero(&'static str),
+    #[error("no markets configured")]
+    NoMarkets,
+    #[error("market {symbol}: {reason}")]
+    InvalidMarket {
+        symbol: Symbol,
+        reason: &'static str,
+    },
+    #[error("simulation settings for {0}, which is not a configured market")]
+    UnknownMarket(Symbol),
+    #[error("simulation of {symbol}: {reason}")]
+    InvalidSimulation {
+        symbol: Symbol,
+        reason: &'static str,
+    },
+    #[error("replay: {0}")]
+    InvalidReplay(&'static str),
+}
+
+#[derive(Error, Debug)]
+pub enum SinkError {
+    #[error("invalid sink: {0}, expected stdout, file:<path>, rotate:<max MB>[,<max age>][,gzip|zstd]:<prefix>, redis:<url>[#<channel>], redis-stream:<url>[#<max len>] or memory:<capacity>")]
+    InvalidSpec(String),
+    #[error("unknown compression: {0}, expected none, gzip or zstd")]
+    UnknownCompression(String),
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("json error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("redis error: {0}")]
+    Redis(#[from] redis::RedisError),
+    #[error("redis is unreachable, messages are queued until it is back")]
+    Unavailable,
+    #[error("the sink has shut down")]
+    Closed,
+}
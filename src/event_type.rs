@@ -1,5 +1,9 @@
-use serde::{Deserialize, Serialize};
+use crate::error::ParseError;
+use crate::subscrib_stream::Symbol;
+use serde::{de, Deserialize, Deserializer, Serialize};
+use serde_json::Value;
 use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
 
 pub mod book_ticker;
 pub mod depth;
@@ -9,11 +13,11 @@ pub mod trade;
 
 pub use book_ticker::BookTickerStream;
 pub use depth::DepthStream;
-pub use kline::KLineStream;
+pub use kline::{KLineStream, KlineInterval};
 pub use ticker::TickerStream;
 pub use trade::TradeStream;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum EventType {
     #[serde(rename = "kline")]
     Kline,
@@ -27,19 +31,29 @@ pub enum EventType {
     BookTicker,
 }
 
-impl From<String> for EventType {
-    fn from(event_type: String) -> Self {
-        match event_type.as_str() {
-            "kline" => EventType::Kline,
-            "ticker" => EventType::Ticker,
-            "trade" => EventType::Trade,
-            "depth" => EventType::Depth,
-            "bookTicker" => EventType::BookTicker,
-            _ => panic!("Invalid event type"),
+impl FromStr for EventType {
+    type Err = ParseError;
+
+    fn from_str(event_type: &str) -> Result<Self, Self::Err> {
+        match event_type {
+            "kline" => Ok(EventType::Kline),
+            "ticker" => Ok(EventType::Ticker),
+            "trade" => Ok(EventType::Trade),
+            "depth" => Ok(EventType::Depth),
+            "bookTicker" => Ok(EventType::BookTicker),
+            _ => Err(ParseError::UnknownEventType(event_type.to_string())),
         }
     }
 }
 
+impl TryFrom<String> for EventType {
+    type Error = ParseError;
+
+    fn try_from(event_type: String) -> Result<Self, Self::Error> {
+        event_type.parse()
+    }
+}
+
 impl Display for EventType {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
@@ -51,3 +65,66 @@ impl Display for EventType {
         }
     }
 }
+
+// Any stream payload, told apart by its `e` field.
+#[derive(Serialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum Event {
+    Kline(KLineStream),
+    Ticker(TickerStream),
+    Trade(TradeStream),
+    Depth(DepthStream),
+    BookTicker(BookTickerStream),
+}
+
+impl Event {
+    pub fn event_type(&self) -> EventType {
+        match self {
+            Event::Kline(_) => EventType::Kline,
+            Event::Ticker(_) => EventType::Ticker,
+            Event::Trade(_) => EventType::Trade,
+            Event::Depth(_) => EventType::Depth,
+            Event::BookTicker(_) => EventType::BookTicker,
+        }
+    }
+
+    pub fn event_time(&self) -> u64 {
+        match self {
+            Event::Kline(event) => event.event_time(),
+            Event::Ticker(event) => event.event_time(),
+            Event::Trade(event) => event.event_time(),
+            Event::Depth(event) => event.event_time(),
+            Event::BookTicker(event) => event.event_time(),
+        }
+    }
+
+    pub fn symbol(&self) -> &Symbol {
+        match self {
+            Event::Kline(event) => event.symbol(),
+            Event::Ticker(event) => event.symbol(),
+            Event::Trade(event) => event.symbol(),
+            Event::Depth(event) => event.symbol(),
+            Event::BookTicker(event) => event.symbol(),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Event {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        let event_type = value
+            .get("e")
+            .and_then(Value::as_str)
+            .ok_or_else(|| de::Error::missing_field("e"))?
+            .parse::<EventType>()
+            .map_err(de::Error::custom)?;
+        let event = match event_type {
+            EventType::Kline => serde_json::from_value(value).map(Event::Kline),
+            EventType::Ticker => serde_json::from_value(value).map(Event::Ticker),
+            EventType::Trade => serde_json::from_value(value).map(Event::Trade),
+            EventType::Depth => serde_json::from_value(value).map(Event::Depth),
+            EventType::BookTicker => serde_json::from_value(value).map(Event::BookTicker),
+        };
+        event.map_err(de::Error::custom)
+    }
+}
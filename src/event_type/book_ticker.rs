@@ -1,11 +1,12 @@
 use super::EventType;
+use crate::envelope::StreamEnvelope;
+use crate::simulator::MarketSimulator;
 use crate::subscrib_stream::Symbol;
 use crate::UpdataStream;
-use rand::rngs::ThreadRng;
-use rand::Rng;
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BookTickerStream {
     /*
       {
@@ -27,13 +28,13 @@ pub struct BookTickerStream {
     #[serde(rename = "s")]
     symbol: Symbol,
     #[serde(rename = "a")]
-    inside_ask_price: String,
+    inside_ask_price: Decimal,
     #[serde(rename = "A")]
-    inside_ask_quantity: String,
+    inside_ask_quantity: Decimal,
     #[serde(rename = "b")]
-    inside_bid_price: String,
+    inside_bid_price: Decimal,
     #[serde(rename = "B")]
-    inside_bid_quantity: String,
+    inside_bid_quantity: Decimal,
     #[serde(rename = "u")]
     update_id: String,
     #[serde(rename = "T")]
@@ -46,29 +47,74 @@ impl BookTickerStream {
             event_type: EventType::BookTicker,
             event_time: 0,
             symbol,
-            inside_ask_price: "0.0".to_string(),
-            inside_ask_quantity: "0.0".to_string(),
-            inside_bid_price: "0.0".to_string(),
-            inside_bid_quantity: "0.0".to_string(),
+            inside_ask_price: Decimal::ZERO,
+            inside_ask_quantity: Decimal::ZERO,
+            inside_bid_price: Decimal::ZERO,
+            inside_bid_quantity: Decimal::ZERO,
             update_id: "0".to_string(),
             engine_timestamp: 0,
         }
     }
+
+    pub fn event_type(&self) -> EventType {
+        self.event_type
+    }
+
+    pub fn event_time(&self) -> u64 {
+        self.event_time
+    }
+
+    pub fn symbol(&self) -> &Symbol {
+        &self.symbol
+    }
+
+    pub fn inside_ask_price(&self) -> Decimal {
+        self.inside_ask_price
+    }
+
+    pub fn inside_ask_quantity(&self) -> Decimal {
+        self.inside_ask_quantity
+    }
+
+    pub fn inside_bid_price(&self) -> Decimal {
+        self.inside_bid_price
+    }
+
+    pub fn inside_bid_quantity(&self) -> Decimal {
+        self.inside_bid_quantity
+    }
+
+    pub fn update_id(&self) -> &str {
+        &self.update_id
+    }
+
+    pub fn engine_timestamp(&self) -> u64 {
+        self.engine_timestamp
+    }
 }
 
 impl UpdataStream for BookTickerStream {
-    fn update(&mut self, time: u64, mut rng: ThreadRng) {
-        self.event_time = time;
-        self.engine_timestamp = time;
-        self.inside_ask_price = rng.gen_range(140.0..190.0).to_string();
-        self.inside_ask_quantity = rng.gen_range(0.0..10.0).to_string();
-        self.inside_bid_price = rng.gen_range(140.0..190.0).to_string();
-        self.inside_bid_quantity = rng.gen_range(0.0..10.0).to_string();
-        self.update_id = rng.gen_range(1000000..9999999).to_string();
+    fn update(&mut self, market: &MarketSimulator) -> bool {
+        let update_id = market.update_id().to_string();
+        if update_id == self.update_id {
+            return false;
+        }
+        let (Some((ask_price, ask_quantity)), Some((bid_price, bid_quantity))) =
+            (market.best_ask(), market.best_bid())
+        else {
+            return false;
+        };
+        self.event_time = market.time();
+        self.engine_timestamp = market.engine_time();
+        self.inside_ask_price = ask_price;
+        self.inside_ask_quantity = ask_quantity;
+        self.inside_bid_price = bid_price;
+        self.inside_bid_quantity = bid_quantity;
+        self.update_id = update_id;
+        true
     }
 
-    fn to_message(&self) -> tokio_tungstenite::tungstenite::Message {
-        let message = serde_json::to_string_pretty(self).unwrap();
-        tokio_tungstenite::tungstenite::Message::Text(message)
+    fn to_message(&self, stream_name: &str) -> tokio_tungstenite::tungstenite::Message {
+        StreamEnvelope::new(stream_name, self).to_message()
     }
 }
@@ -1,11 +1,13 @@
 use super::EventType;
+use crate::envelope::StreamEnvelope;
+use crate::simulator::MarketSimulator;
 use crate::subscrib_stream::Symbol;
 use crate::UpdataStream;
-use rand::rngs::ThreadRng;
-use rand::Rng;
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct DepthStream {
     /*
       {
@@ -40,9 +42,9 @@ pub struct DepthStream {
     #[serde(rename = "s")]
     symbol: Symbol,
     #[serde(rename = "a")]
-    asks: Vec<Vec<String>>,
+    asks: Vec<(Decimal, Decimal)>,
     #[serde(rename = "b")]
-    bids: Vec<Vec<String>>,
+    bids: Vec<(Decimal, Decimal)>,
     #[serde(rename = "U")]
     first_update_id: u64,
     #[serde(rename = "u")]
@@ -57,33 +59,75 @@ impl DepthStream {
             event_type: EventType::Depth,
             event_time: 0,
             symbol,
-            asks: vec![vec!["0.0".to_string(), "0.0".to_string()]],
-            bids: vec![vec!["0.0".to_string(), "0.0".to_string()]],
+            asks: vec![(Decimal::ZERO, Decimal::ZERO)],
+            bids: vec![(Decimal::ZERO, Decimal::ZERO)],
             first_update_id: 0,
             final_update_id: 0,
             engine_timestamp: 0,
         }
     }
+
+    pub fn event_type(&self) -> EventType {
+        self.event_type
+    }
+
+    pub fn event_time(&self) -> u64 {
+        self.event_time
+    }
+
+    pub fn symbol(&self) -> &Symbol {
+        &self.symbol
+    }
+
+    pub fn asks(&self) -> &[(Decimal, Decimal)] {
+        &self.asks
+    }
+
+    pub fn bids(&self) -> &[(Decimal, Decimal)] {
+        &self.bids
+    }
+
+    pub fn first_update_id(&self) -> u64 {
+        self.first_update_id
+    }
+
+    pub fn final_update_id(&self) -> u64 {
+        self.final_update_id
+    }
+
+    pub fn engine_timestamp(&self) -> u64 {
+        self.engine_timestamp
+    }
 }
 
 impl UpdataStream for DepthStream {
-    fn update(&mut self, time: u64, mut rng: ThreadRng) {
-        self.event_time = time;
-        self.engine_timestamp = time;
-        self.first_update_id += 1;
-        self.final_update_id += 1;
-        self.asks = vec![vec![
-            rng.gen_range(140.0..190.0).to_string(),
-            rng.gen_range(0.0..10.0).to_string(),
-        ]];
-        self.bids = vec![vec![
-            (self.asks[0][0].parse::<f64>().unwrap() - rng.gen_range(0.0..10.0)).to_string(),
-            (self.asks[0][1].parse::<f64>().unwrap() - rng.gen_range(0.0..10.0)).to_string(),
-        ]];
+    fn update(&mut self, market: &MarketSimulator) -> bool {
+        let mut updates = market.book_updates_since(self.final_update_id).peekable();
+        let Some(first_update_id) = updates.peek().map(|update| update.update_id) else {
+            return false;
+        };
+        let mut asks = BTreeMap::new();
+        let mut bids = BTreeMap::new();
+        for update in updates {
+            asks.extend(update.asks.iter().copied());
+            bids.extend(update.bids.iter().copied());
+            self.final_update_id = update.update_id;
+        }
+        self.event_time = market.time();
+        self.engine_timestamp = market.engine_time();
+        self.first_update_id = first_update_id;
+        self.asks = asks.into_iter().collect();
+        self.bids = bids.into_iter().rev().collect();
+        true
+    }
+
+    // A new subscriber starts from the book as it is now, so its first diff follows on from
+    // a snapshot taken before the market steps again.
+    fn start(&mut self, market: &MarketSimulator) {
+        self.final_update_id = market.update_id();
     }
 
-    fn to_message(&self) -> tokio_tungstenite::tungstenite::Message {
-        let message = serde_json::to_string_pretty(self).unwrap();
-        tokio_tungstenite::tungstenite::Message::Text(message)
+    fn to_message(&self, stream_name: &str) -> tokio_tungstenite::tungstenite::Message {
+        StreamEnvelope::new(stream_name, self).to_message()
     }
 }
@@ -1,11 +1,209 @@
 use super::EventType;
+use crate::envelope::StreamEnvelope;
+use crate::error::ParseError;
+use crate::market::Market;
+use crate::simulator::MarketSimulator;
 use crate::subscrib_stream::Symbol;
 use crate::UpdataStream;
-use rand::rngs::ThreadRng;
-use rand::Rng;
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
 
-#[derive(Serialize, Deserialize, Debug)]
+const MINUTE: u64 = 60;
+const HOUR: u64 = 60 * MINUTE;
+const DAY: u64 = 24 * HOUR;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KlineInterval {
+    #[default]
+    #[serde(rename = "1m")]
+    OneMinute,
+    #[serde(rename = "3m")]
+    ThreeMinutes,
+    #[serde(rename = "5m")]
+    FiveMinutes,
+    #[serde(rename = "15m")]
+    FifteenMinutes,
+    #[serde(rename = "30m")]
+    ThirtyMinutes,
+    #[serde(rename = "1h")]
+    OneHour,
+    #[serde(rename = "2h")]
+    TwoHours,
+    #[serde(rename = "4h")]
+    FourHours,
+    #[serde(rename = "6h")]
+    SixHours,
+    #[serde(rename = "8h")]
+    EightHours,
+    #[serde(rename = "12h")]
+    TwelveHours,
+    #[serde(rename = "1d")]
+    OneDay,
+    #[serde(rename = "3d")]
+    ThreeDays,
+    #[serde(rename = "1w")]
+    OneWeek,
+    #[serde(rename = "1month")]
+    OneMonth,
+}
+
+impl KlineInterval {
+    // Start of the window containing `time`, both in Unix seconds.
+    pub fn window_start(&self, time: u64) -> u64 {
+        match self {
+            // The Unix epoch was a Thursday, weeks start on Monday.
+            KlineInterval::OneWeek => {
+                let days = time / DAY;
+                (days - (days + 3) % 7) * DAY
+            }
+            KlineInterval::OneMonth => {
+                let (year, month, _) = civil_from_days(time / DAY);
+                days_from_civil(year, month, 1) * DAY
+            }
+            _ => time - time % self.fixed_secs(),
+        }
+    }
+
+    // End (exclusive) of the window starting at `start`, both in Unix seconds.
+    pub fn window_close(&self, start: u64) -> u64 {
+        match self {
+            KlineInterval::OneMonth => {
+                let (year, month, _) = civil_from_days(start / DAY);
+                let (year, month) = if month == 12 {
+                    (year + 1, 1)
+                } else {
+                    (year, month + 1)
+                };
+                days_from_civil(year, month, 1) * DAY
+            }
+            _ => start + self.fixed_secs(),
+        }
+    }
+
+    fn fixed_secs(&self) -> u64 {
+        match self {
+            KlineInterval::OneMinute => MINUTE,
+            KlineInterval::ThreeMinutes => 3 * MINUTE,
+            KlineInterval::FiveMinutes => 5 * MINUTE,
+            KlineInterval::FifteenMinutes => 15 * MINUTE,
+            KlineInterval::ThirtyMinutes => 30 * MINUTE,
+            KlineInterval::OneHour => HOUR,
+            KlineInterval::TwoHours => 2 * HOUR,
+            KlineInterval::FourHours => 4 * HOUR,
+            KlineInterval::SixHours => 6 * HOUR,
+            KlineInterval::EightHours => 8 * HOUR,
+            KlineInterval::TwelveHours => 12 * HOUR,
+            KlineInterval::OneDay => DAY,
+            KlineInterval::ThreeDays => 3 * DAY,
+            KlineInterval::OneWeek => 7 * DAY,
+            KlineInterval::OneMonth => 31 * DAY,
+        }
+    }
+}
+
+impl FromStr for KlineInterval {
+    type Err = ParseError;
+
+    fn from_str(interval: &str) -> Result<Self, Self::Err> {
+        match interval {
+            "1m" => Ok(KlineInterval::OneMinute),
+            "3m" => Ok(KlineInterval::ThreeMinutes),
+            "5m" => Ok(KlineInterval::FiveMinutes),
+            "15m" => Ok(KlineInterval::FifteenMinutes),
+            "30m" => Ok(KlineInterval::ThirtyMinutes),
+            "1h" => Ok(KlineInterval::OneHour),
+            "2h" => Ok(KlineInterval::TwoHours),
+            "4h" => Ok(KlineInterval::FourHours),
+            "6h" => Ok(KlineInterval::SixHours),
+            "8h" => Ok(KlineInterval::EightHours),
+            "12h" => Ok(KlineInterval::TwelveHours),
+            "1d" => Ok(KlineInterval::OneDay),
+            "3d" => Ok(KlineInterval::ThreeDays),
+            "1w" => Ok(KlineInterval::OneWeek),
+            "1month" => Ok(KlineInterval::OneMonth),
+            _ => Err(ParseError::UnknownInterval(interval.to_string())),
+        }
+    }
+}
+
+impl Display for KlineInterval {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let interval = match self {
+            KlineInterval::OneMinute => "1m",
+            KlineInterval::ThreeMinutes => "3m",
+            KlineInterval::FiveMinutes => "5m",
+            KlineInterval::FifteenMinutes => "15m",
+            KlineInterval::ThirtyMinutes => "30m",
+            KlineInterval::OneHour => "1h",
+            KlineInterval::TwoHours => "2h",
+            KlineInterval::FourHours => "4h",
+            KlineInterval::SixHours => "6h",
+            KlineInterval::EightHours => "8h",
+            KlineInterval::TwelveHours => "12h",
+            KlineInterval::OneDay => "1d",
+            KlineInterval::ThreeDays => "3d",
+            KlineInterval::OneWeek => "1w",
+            KlineInterval::OneMonth => "1month",
+        };
+        write!(f, "{}", interval)
+    }
+}
+
+// Howard Hinnant's `civil_from_days`/`days_from_civil`, restricted to dates after 1970.
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let yoe = year - era * 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Candle {
+    open: Decimal,
+    high: Decimal,
+    low: Decimal,
+    close: Decimal,
+    volume: Decimal,
+}
+
+impl Candle {
+    fn open_at(price: Decimal) -> Self {
+        Self {
+            open: price,
+            high: price,
+            low: price,
+            close: price,
+            volume: Decimal::ZERO,
+        }
+    }
+
+    fn trade(&mut self, price: Decimal, quantity: Decimal) {
+        self.high = self.high.max(price);
+        self.low = self.low.min(price);
+        self.close = price;
+        self.volume += quantity;
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct KLineStream {
     /*
       {
@@ -34,56 +232,229 @@ pub struct KLineStream {
     #[serde(rename = "T")]
     kline_close_time: u64,
     #[serde(rename = "o")]
-    open_price: String,
+    open_price: Decimal,
     #[serde(rename = "c")]
-    close_price: String,
+    close_price: Decimal,
     #[serde(rename = "h")]
-    high_price: String,
+    high_price: Decimal,
     #[serde(rename = "l")]
-    low_price: String,
+    low_price: Decimal,
     #[serde(rename = "v")]
-    base_asset_volume: String,
+    base_asset_volume: Decimal,
     #[serde(rename = "n")]
     number_of_trades: u64,
     #[serde(rename = "X")]
     is_kline_closed: bool,
+    #[serde(skip)]
+    interval: KlineInterval,
+    #[serde(skip)]
+    candle: Option<Candle>,
+    #[serde(skip)]
+    last_trade_id: u64,
 }
 
 impl KLineStream {
-    pub fn new(symbol: Symbol) -> Self {
+    pub fn new(symbol: Symbol, interval: KlineInterval) -> Self {
         Self {
             event_type: EventType::Kline,
             event_time: 0,
             symbol,
             kline_start_time: 0,
             kline_close_time: 0,
-            open_price: "0.0".to_string(),
-            close_price: "0.0".to_string(),
-            high_price: "0.0".to_string(),
-            low_price: "0.0".to_string(),
-            base_asset_volume: "0.0".to_string(),
+            open_price: Decimal::ZERO,
+            close_price: Decimal::ZERO,
+            high_price: Decimal::ZERO,
+            low_price: Decimal::ZERO,
+            base_asset_volume: Decimal::ZERO,
             number_of_trades: 0,
             is_kline_closed: false,
+            interval,
+            candle: None,
+            last_trade_id: 0,
         }
     }
+
+    pub fn event_type(&self) -> EventType {
+        self.event_type
+    }
+
+    pub fn event_time(&self) -> u64 {
+        self.event_time
+    }
+
+    pub fn symbol(&self) -> &Symbol {
+        &self.symbol
+    }
+
+    pub fn kline_start_time(&self) -> u64 {
+        self.kline_start_time
+    }
+
+    pub fn kline_close_time(&self) -> u64 {
+        self.kline_close_time
+    }
+
+    pub fn open_price(&self) -> Decimal {
+        self.open_price
+    }
+
+    pub fn close_price(&self) -> Decimal {
+        self.close_price
+    }
+
+    pub fn high_price(&self) -> Decimal {
+        self.high_price
+    }
+
+    pub fn low_price(&self) -> Decimal {
+        self.low_price
+    }
+
+    pub fn base_asset_volume(&self) -> Decimal {
+        self.base_asset_volume
+    }
+
+    pub fn number_of_trades(&self) -> u64 {
+        self.number_of_trades
+    }
+
+    pub fn is_kline_closed(&self) -> bool {
+        self.is_kline_closed
+    }
+
+    fn open_window(&mut self, start: u64, price: Decimal) {
+        self.kline_start_time = start;
+        self.kline_close_time = self.interval.window_close(start);
+        self.number_of_trades = 0;
+        self.is_kline_closed = false;
+        self.candle = Some(Candle::open_at(price));
+    }
+
+    fn render(&mut self, candle: Candle, market: &Market) {
+        self.open_price = candle.open;
+        self.close_price = candle.close;
+        self.high_price = candle.high;
+        self.low_price = candle.low;
+        self.base_asset_volume = market.round_quantity(candle.volume);
+    }
 }
 
 impl UpdataStream for KLineStream {
-    fn update(&mut self, time: u64, mut rng: ThreadRng) {
-        self.event_time = time;
-        self.kline_start_time = time;
-        self.kline_close_time = time;
-        self.open_price = rng.gen_range(140.0..190.0).to_string();
-        self.close_price = rng.gen_range(140.0..190.0).to_string();
-        self.high_price = rng.gen_range(140.0..190.0).to_string();
-        self.low_price = rng.gen_range(140.0..190.0).to_string();
-        self.base_asset_volume = rng.gen_range(0.0..10.0).to_string();
-        self.number_of_trades += 1;
-        self.is_kline_closed = rng.gen_bool(0.5);
-    }
-
-    fn to_message(&self) -> tokio_tungstenite::tungstenite::Message {
-        let message = serde_json::to_string_pretty(self).unwrap();
-        tokio_tungstenite::tungstenite::Message::Text(message)
+    fn update(&mut self, market: &MarketSimulator) -> bool {
+        self.event_time = market.time();
+        let start = self.interval.window_start(market.time() / 1_000_000);
+        match self.candle {
+            // The window rolled over: publish the finished candle once, unchanged.
+            Some(_) if !self.is_kline_closed && start != self.kline_start_time => {
+                self.is_kline_closed = true;
+                return true;
+            }
+            Some(candle) if self.is_kline_closed => self.open_window(start, candle.close),
+            Some(_) => {}
+            None => {
+                self.last_trade_id = market.last_trade().map_or(0, |trade| trade.id);
+                self.open_window(start, market.last_price());
+            }
+        }
+        let mut candle = self.candle.unwrap_or_default();
+        for trade in market.trades_since(self.last_trade_id) {
+            candle.trade(trade.price, trade.quantity);
+            self.number_of_trades += 1;
+            self.last_trade_id = trade.id;
+        }
+        self.candle = Some(candle);
+        self.render(candle, market.market());
+        true
+    }
+
+    fn to_message(&self, stream_name: &str) -> tokio_tungstenite::tungstenite::Message {
+        StreamEnvelope::new(stream_name, self).to_message()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::market::MarketRegistry;
+    use crate::simulator::SimulationConfig;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use std::time::Duration;
+
+    // 2024-01-01 00:00:00 UTC, a Monday.
+    const NEW_YEAR: u64 = 1_704_067_200;
+
+    #[test]
+    fn fixed_windows_align_to_the_epoch() {
+        let time = NEW_YEAR + 2800;
+        assert_eq!(KlineInterval::OneHour.window_start(time), NEW_YEAR);
+        assert_eq!(
+            KlineInterval::OneHour.window_close(NEW_YEAR),
+            NEW_YEAR + HOUR
+        );
+        assert_eq!(
+            KlineInterval::FifteenMinutes.window_start(time),
+            NEW_YEAR + 2700
+        );
+    }
+
+    #[test]
+    fn weeks_start_on_monday() {
+        let wednesday_noon = NEW_YEAR + 2 * DAY + 12 * HOUR;
+        assert_eq!(
+            KlineInterval::OneWeek.window_start(wednesday_noon),
+            NEW_YEAR
+        );
+        assert_eq!(KlineInterval::OneWeek.window_start(NEW_YEAR), NEW_YEAR);
+        let sunday_night = NEW_YEAR + 7 * DAY - 1;
+        assert_eq!(KlineInterval::OneWeek.window_start(sunday_night), NEW_YEAR);
+        assert_eq!(
+            KlineInterval::OneWeek.window_close(NEW_YEAR),
+            NEW_YEAR + 7 * DAY
+        );
+    }
+
+    #[test]
+    fn months_follow_the_calendar() {
+        let month = KlineInterval::OneMonth;
+        // Leap February: 2024-02-15 falls in 2024-02-01 .. 2024-03-01.
+        assert_eq!(month.window_start(1_707_984_000), 1_706_745_600);
+        assert_eq!(month.window_close(1_706_745_600), 1_709_251_200);
+        // The last second of 2023 is in December, which closes at the new year.
+        assert_eq!(month.window_start(NEW_YEAR - 1), 1_701_388_800);
+        assert_eq!(month.window_close(1_701_388_800), NEW_YEAR);
+    }
+
+    #[test]
+    fn rolled_over_candle_is_published_closed_once() {
+        let market = MarketRegistry::with_defaults()
+            .get("SOL_USDC")
+            .cloned()
+            .unwrap();
+        let mut simulator = SimulationConfig::default().simulator(market.clone());
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut kline = KLineStream::new(market.symbol.clone(), KlineInterval::OneMinute);
+        let mut step = |kline: &mut KLineStream, seconds: u64| {
+            simulator.step(seconds * 1_000_000, Duration::from_secs(1), &mut rng);
+            assert!(kline.update(&simulator));
+        };
+
+        step(&mut kline, NEW_YEAR + 30);
+        step(&mut kline, NEW_YEAR + 50);
+        assert_eq!(kline.kline_start_time(), NEW_YEAR);
+        assert_eq!(kline.kline_close_time(), NEW_YEAR + MINUTE);
+        assert!(!kline.is_kline_closed());
+        let open = kline.clone();
+
+        step(&mut kline, NEW_YEAR + 70);
+        assert!(kline.is_kline_closed());
+        assert_eq!(kline.kline_start_time(), NEW_YEAR);
+        assert_eq!(kline.close_price(), open.close_price());
+        assert_eq!(kline.number_of_trades(), open.number_of_trades());
+
+        step(&mut kline, NEW_YEAR + 80);
+        assert!(!kline.is_kline_closed());
+        assert_eq!(kline.kline_start_time(), NEW_YEAR + MINUTE);
+        assert_eq!(kline.open_price(), open.close_price());
     }
 }
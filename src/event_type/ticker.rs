@@ -1,11 +1,12 @@
 use super::EventType;
+use crate::envelope::StreamEnvelope;
+use crate::simulator::MarketSimulator;
 use crate::subscrib_stream::Symbol;
 use crate::UpdataStream;
-use rand::rngs::ThreadRng;
-use rand::Rng;
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TickerStream {
     /*
       {
@@ -28,17 +29,17 @@ pub struct TickerStream {
     #[serde(rename = "s")]
     symbol: Symbol,
     #[serde(rename = "o")]
-    first_price: String,
+    first_price: Decimal,
     #[serde(rename = "c")]
-    last_price: String,
+    last_price: Decimal,
     #[serde(rename = "h")]
-    high_price: String,
+    high_price: Decimal,
     #[serde(rename = "l")]
-    low_price: String,
+    low_price: Decimal,
     #[serde(rename = "v")]
-    base_asset_volume: String,
+    base_asset_volume: Decimal,
     #[serde(rename = "V")]
-    quote_asset_volume: String,
+    quote_asset_volume: Decimal,
     #[serde(rename = "n")]
     number_of_trades: u64,
 }
@@ -49,31 +50,81 @@ impl TickerStream {
             event_type: EventType::Ticker,
             event_time: 0,
             symbol,
-            first_price: "0.0".to_string(),
-            last_price: "0.0".to_string(),
-            high_price: "0.0".to_string(),
-            low_price: "0.0".to_string(),
-            base_asset_volume: "0.0".to_string(),
-            quote_asset_volume: "0.0".to_string(),
+            first_price: Decimal::ZERO,
+            last_price: Decimal::ZERO,
+            high_price: Decimal::ZERO,
+            low_price: Decimal::ZERO,
+            base_asset_volume: Decimal::ZERO,
+            quote_asset_volume: Decimal::ZERO,
             number_of_trades: 0,
         }
     }
+
+    pub fn event_type(&self) -> EventType {
+        self.event_type
+    }
+
+    pub fn event_time(&self) -> u64 {
+        self.event_time
+    }
+
+    pub fn symbol(&self) -> &Symbol {
+        &self.symbol
+    }
+
+    pub fn first_price(&self) -> Decimal {
+        self.first_price
+    }
+
+    pub fn last_price(&self) -> Decimal {
+        self.last_price
+    }
+
+    pub fn high_price(&self) -> Decimal {
+        self.high_price
+    }
+
+    pub fn low_price(&self) -> Decimal {
+        self.low_price
+    }
+
+    pub fn base_asset_volume(&self) -> Decimal {
+        self.base_asset_volume
+    }
+
+    pub fn quote_asset_volume(&self) -> Decimal {
+        self.quote_asset_volume
+    }
+
+    pub fn number_of_trades(&self) -> u64 {
+        self.number_of_trades
+    }
 }
 
 impl UpdataStream for TickerStream {
-    fn update(&mut self, time: u64, mut rng: ThreadRng) {
-        self.event_time = time;
-        self.first_price = rng.gen_range(140.0..190.0).to_string();
-        self.last_price = rng.gen_range(140.0..190.0).to_string();
-        self.high_price = rng.gen_range(140.0..190.0).to_string();
-        self.low_price = rng.gen_range(140.0..190.0).to_string();
-        self.base_asset_volume = rng.gen_range(0.0..10.0).to_string();
-        self.quote_asset_volume = rng.gen_range(0.0..10.0).to_string();
-        self.number_of_trades += 1;
+    fn update(&mut self, market: &MarketSimulator) -> bool {
+        let stats = market.stats();
+        let (Some(first), Some(last), Some(high), Some(low)) = (
+            stats.first_price,
+            stats.last_price,
+            stats.high_price,
+            stats.low_price,
+        ) else {
+            return false;
+        };
+        let market_info = market.market();
+        self.event_time = market.time();
+        self.first_price = first;
+        self.last_price = last;
+        self.high_price = high;
+        self.low_price = low;
+        self.base_asset_volume = stats.base_asset_volume;
+        self.quote_asset_volume = market_info.round_price(stats.quote_asset_volume);
+        self.number_of_trades = stats.number_of_trades;
+        true
     }
 
-    fn to_message(&self) -> tokio_tungstenite::tungstenite::Message {
-        let message = serde_json::to_string_pretty(self).unwrap();
-        tokio_tungstenite::tungstenite::Message::Text(message)
+    fn to_message(&self, stream_name: &str) -> tokio_tungstenite::tungstenite::Message {
+        StreamEnvelope::new(stream_name, self).to_message()
     }
 }
@@ -1,11 +1,12 @@
 use super::EventType;
+use crate::envelope::StreamEnvelope;
+use crate::simulator::MarketSimulator;
 use crate::subscrib_stream::Symbol;
 use crate::UpdataStream;
-use rand::rngs::ThreadRng;
-use rand::Rng;
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TradeStream {
     /*
       {
@@ -28,9 +29,9 @@ pub struct TradeStream {
     #[serde(rename = "s")]
     symbol: Symbol,
     #[serde(rename = "p")]
-    price: String,
+    price: Decimal,
     #[serde(rename = "q")]
-    quantity: String,
+    quantity: Decimal,
     #[serde(rename = "b")]
     buyer_order_id: String,
     #[serde(rename = "a")]
@@ -49,8 +50,8 @@ impl TradeStream {
             event_type: EventType::Trade,
             event_time: 0,
             symbol,
-            price: "0.0".to_string(),
-            quantity: "0.0".to_string(),
+            price: Decimal::ZERO,
+            quantity: Decimal::ZERO,
             buyer_order_id: "0".to_string(),
             seller_order_id: "0".to_string(),
             trade_id: 0,
@@ -58,22 +59,65 @@ impl TradeStream {
             is_buyer_the_maker: false,
         }
     }
+
+    pub fn event_type(&self) -> EventType {
+        self.event_type
+    }
+
+    pub fn event_time(&self) -> u64 {
+        self.event_time
+    }
+
+    pub fn symbol(&self) -> &Symbol {
+        &self.symbol
+    }
+
+    pub fn price(&self) -> Decimal {
+        self.price
+    }
+
+    pub fn quantity(&self) -> Decimal {
+        self.quantity
+    }
+
+    pub fn buyer_order_id(&self) -> &str {
+        &self.buyer_order_id
+    }
+
+    pub fn seller_order_id(&self) -> &str {
+        &self.seller_order_id
+    }
+
+    pub fn trade_id(&self) -> u64 {
+        self.trade_id
+    }
+
+    pub fn engine_timestamp(&self) -> u64 {
+        self.engine_timestamp
+    }
+
+    pub fn is_buyer_the_maker(&self) -> bool {
+        self.is_buyer_the_maker
+    }
 }
 
 impl UpdataStream for TradeStream {
-    fn update(&mut self, time: u64, mut rng: ThreadRng) {
-        self.event_time = time;
-        self.engine_timestamp = time;
-        self.price = rng.gen_range(140.0..190.0).to_string();
-        self.quantity = rng.gen_range(0.0..10.0).to_string();
-        self.buyer_order_id = rng.gen_range(1000000..9999999).to_string();
-        self.seller_order_id = rng.gen_range(1000000..9999999).to_string();
-        self.trade_id += 1;
-        self.is_buyer_the_maker = rng.gen_bool(0.5);
+    fn update(&mut self, market: &MarketSimulator) -> bool {
+        let Some(trade) = market.last_trade().filter(|trade| trade.id > self.trade_id) else {
+            return false;
+        };
+        self.event_time = market.time();
+        self.engine_timestamp = trade.time;
+        self.price = trade.price;
+        self.quantity = trade.quantity;
+        self.buyer_order_id = trade.buyer_order_id.to_string();
+        self.seller_order_id = trade.seller_order_id.to_string();
+        self.trade_id = trade.id;
+        self.is_buyer_the_maker = trade.is_buyer_the_maker;
+        true
     }
 
-    fn to_message(&self) -> tokio_tungstenite::tungstenite::Message {
-        let message = serde_json::to_string_pretty(self).unwrap();
-        tokio_tungstenite::tungstenite::Message::Text(message)
+    fn to_message(&self, stream_name: &str) -> tokio_tungstenite::tungstenite::Message {
+        StreamEnvelope::new(stream_name, self).to_message()
     }
 }
@@ -0,0 +1,163 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::time::{sleep_until, Instant};
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::Message;
+
+#[derive(Debug, Clone, Copy)]
+pub struct HeartbeatConfig {
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(10),
+            timeout: Duration::from_secs(20),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum HeartbeatAction {
+    Ping(Message),
+    // A ping went unanswered for longer than the timeout, the connection should be closed.
+    TimedOut,
+}
+
+// Keeps one side of a websocket connection alive: pings go out every `interval` carrying a
+// sequence number and pongs are matched back to them. A ping left unanswered for `timeout`
+// times the connection out. Pings from the peer are answered by tungstenite itself.
+#[derive(Debug)]
+pub struct Heartbeat {
+    config: HeartbeatConfig,
+    next_ping: Instant,
+    sequence: u64,
+    outstanding: VecDeque<(u64, Instant)>,
+    latency: Option<Duration>,
+}
+
+impl Heartbeat {
+    pub fn new(config: HeartbeatConfig) -> Self {
+        Self {
+            config,
+            next_ping: Instant::now() + config.interval,
+            sequence: 0,
+            outstanding: VecDeque::new(),
+            latency: None,
+        }
+    }
+
+    // Waits for the next ping or the pong deadline, whichever comes first. Safe to use
+    // in `select!`, nothing changes until it completes.
+    pub async fn tick(&mut self) -> HeartbeatAction {
+        let deadline = self
+            .outstanding
+            .front()
+            .map(|&(_, sent)| sent + self.config.timeout);
+        sleep_until(deadline.map_or(self.next_ping, |deadline| deadline.min(self.next_ping))).await;
+        if deadline.is_some_and(|deadline| deadline <= Instant::now()) {
+            return HeartbeatAction::TimedOut;
+        }
+        let now = Instant::now();
+        self.sequence += 1;
+        self.outstanding.push_back((self.sequence, now));
+        self.next_ping = now + self.config.interval;
+        HeartbeatAction::Ping(Message::Ping(self.sequence.to_string().into_bytes()))
+    }
+
+    // The round-trip time when the message is a pong to one of our pings.
+    pub fn on_message(&mut self, message: &Message) -> Option<Duration> {
+        match message {
+            Message::Pong(payload) => self.on_pong(payload),
+            _ => None,
+        }
+    }
+
+    // A pong also answers every ping sent before the one it echoes.
+    fn on_pong(&mut self, payload: &[u8]) -> Option<Duration> {
+        let sequence = std::str::from_utf8(payload).ok()?.parse::<u64>().ok()?;
+        let &(_, sent) = self.outstanding.iter().find(|&&(seq, _)| seq == sequence)?;
+        self.outstanding.retain(|&(seq, _)| seq > sequence);
+        let latency = sent.elapsed();
+        self.latency = Some(latency);
+        Some(latency)
+    }
+
+    // Round-trip time of the last answered ping.
+    pub fn latency(&self) -> Option<Duration> {
+        self.latency
+    }
+
+    pub fn close_frame() -> Message {
+        Message::Close(Some(CloseFrame {
+            code: CloseCode::Away,
+            reason: "heartbeat timed out".into(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heartbeat() -> Heartbeat {
+        Heartbeat::new(HeartbeatConfig {
+            interval: Duration::from_secs(10),
+            timeout: Duration::from_secs(20),
+        })
+    }
+
+    async fn ping(heartbeat: &mut Heartbeat) -> Vec<u8> {
+        match heartbeat.tick().await {
+            HeartbeatAction::Ping(Message::Ping(payload)) => payload,
+            action => panic!("expected a ping, got {:?}", action),
+        }
+    }
+
+    #[tokio::test]
+    async fn unanswered_ping_times_out() {
+        tokio::time::pause();
+        let start = Instant::now();
+        let mut heartbeat = heartbeat();
+        assert_eq!(ping(&mut heartbeat).await, b"1");
+        assert_eq!(ping(&mut heartbeat).await, b"2");
+        assert!(matches!(heartbeat.tick().await, HeartbeatAction::TimedOut));
+        // The first ping went out after 10s and was due back 20s later.
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_secs(30) && elapsed < Duration::from_secs(31));
+        let Message::Close(Some(frame)) = Heartbeat::close_frame() else {
+            panic!("expected a close frame");
+        };
+        assert_eq!(frame.code, CloseCode::Away);
+    }
+
+    #[tokio::test]
+    async fn pong_to_an_unknown_ping_is_ignored() {
+        tokio::time::pause();
+        let mut heartbeat = heartbeat();
+        ping(&mut heartbeat).await;
+        assert_eq!(heartbeat.on_message(&Message::Pong(b"7".to_vec())), None);
+        assert_eq!(heartbeat.on_message(&Message::Pong(b"one".to_vec())), None);
+        assert_eq!(heartbeat.latency(), None);
+        ping(&mut heartbeat).await;
+        assert!(matches!(heartbeat.tick().await, HeartbeatAction::TimedOut));
+    }
+
+    #[tokio::test]
+    async fn pong_reports_the_round_trip() {
+        tokio::time::pause();
+        let mut heartbeat = heartbeat();
+        let first = ping(&mut heartbeat).await;
+        let second = ping(&mut heartbeat).await;
+        tokio::time::advance(Duration::from_millis(250)).await;
+        let round_trip = heartbeat.on_message(&Message::Pong(second));
+        assert_eq!(round_trip, Some(Duration::from_millis(250)));
+        assert_eq!(heartbeat.latency(), round_trip);
+        // Answering the second ping also answered the first, so nothing is left to time out.
+        assert_eq!(heartbeat.on_message(&Message::Pong(first)), None);
+        assert_eq!(ping(&mut heartbeat).await, b"3");
+    }
+}
@@ -0,0 +1,415 @@
+use crate::clock::Clock;
+use crate::error::{HubError, ParseError};
+use crate::market::MarketRegistry;
+use crate::order_book::DepthSnapshot;
+use crate::replay::{Replay, ReplaySettings};
+use crate::simulator::{MarketFeed, SimulationConfig};
+use crate::subscrib_stream::{StreamName, Symbol};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+use tokio_tungstenite::tungstenite::Message;
+use tracing::info;
+
+const MIN_INTERVAL: Duration = Duration::from_millis(1);
+
+struct Channel {
+    sender: broadcast::Sender<Message>,
+    subscribers: usize,
+}
+
+struct MarketState {
+    feed: MarketFeed,
+    channels: BTreeMap<String, Channel>,
+}
+
+struct MarketEntry {
+    state: Arc<Mutex<MarketState>>,
+    task: Option<JoinHandle<()>>,
+}
+
+struct ReplayState {
+    settings: ReplaySettings,
+    // None once the replay has finished, which closes every channel.
+    channels: Arc<Mutex<Option<BTreeMap<String, Channel>>>>,
+    task: Option<JoinHandle<()>>,
+}
+
+// Server-wide registry of live streams. Each market is simulated once and every stream is
+// broadcast to all of its subscribers, so clients of the same stream see identical data.
+// A market starts ticking on its first subscription and stops after its last unsubscribe;
+// its simulator is kept so prices carry on from where they were when it is resumed.
+// With a replay every stream is served from the recording instead. The replay starts with
+// the first subscription and keeps going without subscribers, so all streams stay on one
+// timeline; events of streams nobody is subscribed to are dropped. Once it has played
+// through, subscribers are disconnected from its streams and new subscriptions rejected.
+pub struct StreamHub {
+    simulation: SimulationConfig,
+    clock: Arc<dyn Clock>,
+    // Market time a step covers, and the real time between two steps.
+    tick: Duration,
+    interval: Duration,
+    capacity: usize,
+    markets: Mutex<BTreeMap<Symbol, MarketEntry>>,
+    replay: Option<Mutex<ReplayState>>,
+}
+
+impl StreamHub {
+    pub fn new(
+        simulation: SimulationConfig,
+        clock: Arc<dyn Clock>,
+        tick: Duration,
+        capacity: usize,
+    ) -> Self {
+        Self {
+            simulation,
+            clock,
+            tick,
+            interval: tick,
+            capacity,
+            markets: Mutex::new(BTreeMap::new()),
+            replay: None,
+        }
+    }
+
+    // Steps markets `speed` times as often, for a clock running `speed` times faster, but
+    // never more than once a millisecond. Beyond that each step covers more market time, so
+    // prices still keep up with the clock.
+    pub fn with_speed(mut self, speed: f64) -> Self {
+        self.interval = self.tick.div_f64(speed);
+        if self.interval < MIN_INTERVAL {
+            self.interval = MIN_INTERVAL;
+            self.tick = MIN_INTERVAL.mul_f64(speed);
+        }
+        self
+    }
+
+    pub fn with_replay(mut self, settings: ReplaySettings) -> Self {
+        self.replay = Some(Mutex::new(ReplayState {
+            settings,
+            channels: Arc::new(Mutex::new(Some(BTreeMap::new()))),
+            task: None,
+        }));
+        self
+    }
+
+    pub fn subscribe(
+        &self,
+        stream_name: &StreamName,
+    ) -> Result<broadcast::Receiver<Message>, HubError> {
+        if let Some(replay) = &self.replay {
+            return self.subscribe_replay(&mut replay.lock().unwrap(), stream_name);
+        }
+        let mut markets = self.markets.lock().unwrap();
+        let entry = self.market_entry(&mut markets, &stream_name.symbol)?;
+        let receiver = {
+            let mut state = entry.state.lock().unwrap();
+            let MarketState { feed, channels } = &mut *state;
+            let channel = channels.entry(stream_name.to_string()).or_insert_with(|| {
+                info!("Start stream: {}", stream_name);
+                feed.subscribe(stream_name.clone());
+                Channel {
+                    sender: broadcast::channel(self.capacity).0,
+                    subscribers: 0,
+                }
+            });
+            channel.subscribers += 1;
+            channel.sender.subscribe()
+        };
+        if entry.task.is_none() {
+            info!("Start market: {}", stream_name.symbol);
+            entry.task = Some(tokio::spawn(run_market(
+                entry.state.clone(),
+                self.clock.clone(),
+                self.tick,
+                self.interval,
+            )));
+        }
+        Ok(receiver)
+    }
+
+    fn subscribe_replay(
+        &self,
+        replay: &mut ReplayState,
+        stream_name: &StreamName,
+    ) -> Result<broadcast::Receiver<Message>, HubError> {
+        let receiver = {
+            let mut channels = replay.channels.lock().unwrap();
+            let channels = channels.as_mut().ok_or(HubError::ReplayFinished)?;
+            let channel = channels.entry(stream_name.to_string()).or_insert_with(|| {
+                info!("Start stream: {}", stream_name);
+                Channel {
+                    sender: broadcast::channel(self.capacity).0,
+                    subscribers: 0,
+                }
+            });
+            channel.subscribers += 1;
+            channel.sender.subscribe()
+        };
+        if replay.task.is_none() {
+            info!("Start replay");
+            replay.task = Some(tokio::spawn(run_replay(
+                Replay::start(replay.settings.clone()),
+                replay.channels.clone(),
+                self.capacity,
+            )));
+        }
+        Ok(receiver)
+    }
+
+    // The current book of a market, taken between two ticks so it lines up exactly with the
+    // depth events streamed for it.
+    pub fn depth_snapshot(&self, symbol: &Symbol) -> Result<DepthSnapshot, ParseError> {
+        let mut markets = self.markets.lock().unwrap();
+        let entry = self.market_entry(&mut markets, symbol)?;
+        let state = entry.state.lock().unwrap();
+        Ok(state.feed.simulator().depth_snapshot())
+    }
+
+    // A market is set up on first use and kept, so its simulator outlives subscriptions.
+    fn market_entry<'a>(
+        &self,
+        markets: &'a mut BTreeMap<Symbol, MarketEntry>,
+        symbol: &Symbol,
+    ) -> Result<&'a mut MarketEntry, ParseError> {
+        if !markets.contains_key(symbol) {
+            let market = MarketRegistry::global()
+                .read()
+                .unwrap()
+                .get(symbol.as_str())
+                .cloned()
+                .ok_or_else(|| ParseError::UnknownSymbol(symbol.to_string()))?;
+            let state = MarketState {
+                feed: self.simulation.feed(market),
+                channels: BTreeMap::new(),
+            };
+            let entry = MarketEntry {
+                state: Arc::new(Mutex::new(state)),
+                task: None,
+            };
+            markets.insert(symbol.clone(), entry);
+        }
+        Ok(markets.get_mut(symbol).unwrap())
+    }
+
+    pub fn unsubscribe(&self, stream_name: &StreamName) {
+        if let Some(replay) = &self.replay {
+            let replay = replay.lock().unwrap();
+            let mut channels = replay.channels.lock().unwrap();
+            let Some(channels) = channels.as_mut() else {
+                return;
+            };
+            let name = stream_name.to_string();
+            if let Some(channel) = channels.get_mut(&name) {
+                channel.subscribers -= 1;
+                if channel.subscribers == 0 {
+                    info!("Stop stream: {}", stream_name);
+                    channels.remove(&name);
+                }
+            }
+            return;
+        }
+        let mut markets = self.markets.lock().unwrap();
+        let Some(entry) = markets.get_mut(&stream_name.symbol) else {
+            return;
+        };
+        let mut state = entry.state.lock().unwrap();
+        let name = stream_name.to_string();
+        if let Some(channel) = state.channels.get_mut(&name) {
+            channel.subscribers -= 1;
+            if channel.subscribers == 0 {
+                info!("Stop stream: {}", stream_name);
+                state.channels.remove(&name);
+                state.feed.unsubscribe(stream_name);
+            }
+        }
+        if state.feed.is_empty() {
+            if let Some(task) = entry.task.take() {
+                info!("Stop market: {}", stream_name.symbol);
+                task.abort();
+            }
+        }
+    }
+}
+
+async fn run_market(
+    state: Arc<Mutex<MarketState>>,
+    clock: Arc<dyn Clock>,
+    tick: Duration,
+    interval: Duration,
+) {
+    let mut interval = tokio::time::interval(interval);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        let mut guard = state.lock().unwrap();
+        let state = &mut *guard;
+        for (name, message) in state.feed.tick(clock.as_ref(), tick) {
+            if let Some(channel) = state.channels.get(&name) {
+                // No receivers only means every subscriber is between unsubscribe and cleanup.
+                let _ = channel.sender.send(message);
+            }
+        }
+    }
+}
+
+async fn run_replay(
+    mut replay: Replay,
+    channels: Arc<Mutex<Option<BTreeMap<String, Channel>>>>,
+    capacity: usize,
+) {
+    while let Some(event) = replay.next().await {
+        // Unpaced, the replay waits for the slowest subscriber instead of making it skip.
+        while !replay.is_paced()
+            && channels
+                .lock()
+                .unwrap()
+                .as_ref()
+                .and_then(|channels| channels.get(&event.stream))
+                .is_some_and(|channel| channel.sender.len() >= capacity)
+        {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        let channels = channels.lock().unwrap();
+        if let Some(channel) = channels
+            .as_ref()
+            .and_then(|channels| channels.get(&event.stream))
+        {
+            let _ = channel.sender.send(event.into_envelope().to_message());
+        }
+    }
+    info!("Replay finished");
+    // Dropping the senders ends every subscription once its queued events are delivered.
+    channels.lock().unwrap().take();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::SystemClock;
+    use crate::simulator::{PriceModelConfig, SimulationSettings};
+    use tokio::sync::broadcast::error::RecvError;
+
+    fn hub(tick: Duration) -> StreamHub {
+        StreamHub::new(SimulationConfig::default(), Arc::new(SystemClock), tick, 16)
+    }
+
+    // SOL_USDC walks up a dollar a step from 100, so its price tells how far it has got.
+    fn scripted_hub() -> StreamHub {
+        let settings = SimulationSettings {
+            initial_price: 100.0,
+            price_model: PriceModelConfig::Scripted {
+                prices: (1..=1000).map(|step| 100.0 + step as f64).collect(),
+                repeat: false,
+            },
+        };
+        let mut simulation = SimulationConfig::default();
+        simulation.markets.insert(Symbol::new("SOL_USDC"), settings);
+        let clock = Arc::new(SystemClock);
+        StreamHub::new(simulation, clock, Duration::from_millis(100), 64)
+    }
+
+    fn mid_price(hub: &StreamHub) -> f64 {
+        let markets = hub.markets.lock().unwrap();
+        let state = markets[&Symbol::new("SOL_USDC")].state.lock().unwrap();
+        state.feed.simulator().mid_price()
+    }
+
+    fn is_running(hub: &StreamHub) -> bool {
+        hub.markets.lock().unwrap()[&Symbol::new("SOL_USDC")]
+            .task
+            .is_some()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn subscribers_of_a_stream_get_identical_messages() {
+        let hub = hub(Duration::from_millis(100));
+        let stream_name: StreamName = "depth.SOL_USDC".parse().unwrap();
+        let mut first = hub.subscribe(&stream_name).unwrap();
+        let mut second = hub.subscribe(&stream_name).unwrap();
+        for _ in 0..5 {
+            assert_eq!(first.recv().await.unwrap(), second.recv().await.unwrap());
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn market_stops_after_its_last_unsubscribe() {
+        let hub = scripted_hub();
+        let depth: StreamName = "depth.SOL_USDC".parse().unwrap();
+        let trade: StreamName = "trade.SOL_USDC".parse().unwrap();
+        let mut receiver = hub.subscribe(&depth).unwrap();
+        hub.subscribe(&trade).unwrap();
+        receiver.recv().await.unwrap();
+
+        hub.unsubscribe(&depth);
+        assert!(is_running(&hub));
+        hub.unsubscribe(&trade);
+        assert!(!is_running(&hub));
+        let stopped_at = mid_price(&hub);
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(mid_price(&hub), stopped_at);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn resumed_market_carries_on_from_its_price() {
+        let hub = scripted_hub();
+        let stream_name: StreamName = "depth.SOL_USDC".parse().unwrap();
+        let mut receiver = hub.subscribe(&stream_name).unwrap();
+        for _ in 0..3 {
+            receiver.recv().await.unwrap();
+        }
+        hub.unsubscribe(&stream_name);
+        let paused_at = mid_price(&hub);
+        assert!(paused_at >= 103.0);
+
+        let mut receiver = hub.subscribe(&stream_name).unwrap();
+        receiver.recv().await.unwrap();
+        assert!(mid_price(&hub) > paused_at);
+    }
+
+    #[test]
+    fn speed_shortens_the_interval_between_steps() {
+        let hub = hub(Duration::from_millis(100)).with_speed(10.0);
+        assert_eq!(hub.interval, Duration::from_millis(10));
+        assert_eq!(hub.tick, Duration::from_millis(100));
+    }
+
+    #[test]
+    fn speed_beyond_a_step_a_millisecond_lengthens_the_steps() {
+        let hub = hub(Duration::from_millis(100)).with_speed(1000.0);
+        assert_eq!(hub.interval, MIN_INTERVAL);
+        // Each step still covers the market time the clock moves in an interval.
+        assert_eq!(hub.tick, Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn finished_replay_closes_its_streams() {
+        let path =
+            std::env::temp_dir().join(format!("backpack-replay-{}.ndjson", std::process::id()));
+        let line = "{\"receivedAt\":1,\"stream\":\"trade.SOL_USDC\",\"data\":{}}\n";
+        std::fs::write(&path, line.repeat(3)).unwrap();
+        let settings = ReplaySettings {
+            files: vec![path.clone()],
+            max_speed: true,
+            ..ReplaySettings::default()
+        };
+        let hub = hub(Duration::from_millis(100)).with_replay(settings);
+        let stream_name: StreamName = "trade.SOL_USDC".parse().unwrap();
+
+        let mut receiver = hub.subscribe(&stream_name).unwrap();
+        for _ in 0..3 {
+            receiver.recv().await.unwrap();
+        }
+        assert_eq!(receiver.recv().await, Err(RecvError::Closed));
+        assert_eq!(
+            hub.subscribe(&stream_name).unwrap_err(),
+            HubError::ReplayFinished
+        );
+        hub.unsubscribe(&stream_name);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
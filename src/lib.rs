@@ -1,20 +1,60 @@
-use rand::rngs::ThreadRng;
 use tokio_tungstenite::tungstenite::protocol::Message;
 
+pub mod client;
+pub mod clock;
+pub mod config;
+pub mod envelope;
+pub mod error;
 pub mod event_type;
+pub mod heartbeat;
+pub mod hub;
+pub mod market;
+pub mod order_book;
+pub mod recording;
+pub mod replay;
+pub mod response;
+pub mod simulator;
+pub mod sink;
 pub mod subscrib_stream;
 
+pub use client::{BackpackClient, ClientConfig, ClientEvent, ClientEvents, ReconnectPolicy};
+pub use clock::{Clock, SimulatedClock, SystemClock};
+pub use config::{ConnectionLimits, HeartbeatSettings, ServerConfig};
+pub use envelope::{Frame, StreamEnvelope};
+pub use error::{BookError, ClientError, ConfigError, HubError, ParseError, SinkError};
 pub use event_type::*;
+pub use heartbeat::{Heartbeat, HeartbeatAction, HeartbeatConfig};
+pub use hub::StreamHub;
+pub use market::{Market, MarketRegistry, MarketType};
+pub use order_book::{DepthSnapshot, OrderBook};
+pub use recording::{Compression, RecordedEvent};
+pub use replay::{Replay, ReplaySettings};
+pub use response::{ErrorCode, Response, ResponseError};
+pub use rust_decimal::Decimal;
+pub use simulator::{
+    market_rng, BookUpdate, MarketFeed, MarketSimulator, PriceModel, PriceModelConfig,
+    SimulationConfig,
+};
+pub use sink::{
+    FileSink, MemorySink, MultiSink, RedisPublishSink, RedisStreamReader, RedisStreamSink,
+    RotatingFileSink, Sink, SinkSpec, StdoutSink, StreamRecord,
+};
 pub use subscrib_stream::*;
 
 pub trait UpdataStream: Send {
-    fn update(&mut self, time: u64, rng: ThreadRng);
-    fn to_message(&self) -> Message;
+    // Pulls the latest state out of the market, returns false when there is nothing new to publish.
+    fn update(&mut self, market: &MarketSimulator) -> bool;
+    // Called once on subscription, before the market is next stepped.
+    fn start(&mut self, _market: &MarketSimulator) {}
+    fn to_message(&self, stream_name: &str) -> Message;
 }
 
 pub fn parse_stream_name(stream_name: StreamName) -> Box<dyn UpdataStream> {
     match stream_name.stream {
-        EventType::Kline => Box::new(KLineStream::new(stream_name.symbol)),
+        EventType::Kline => Box::new(KLineStream::new(
+            stream_name.symbol,
+            stream_name.interval.unwrap_or_default(),
+        )),
         EventType::Ticker => Box::new(TickerStream::new(stream_name.symbol)),
         EventType::Trade => Box::new(TradeStream::new(stream_name.symbol)),
         EventType::Depth => Box::new(DepthStream::new(stream_name.symbol)),
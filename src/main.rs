@@ -1,12 +1,14 @@
 use backpack::subscrib_stream::*;
 
-fn main() {
-    let stream_name = "depth.SOL_USDC".to_string().into();
+fn main() -> anyhow::Result<()> {
+    let stream_name = "depth.SOL_USDC".parse()?;
     let method = Method::Subscribe;
     let subscrib_stream = SubscribStream {
+        id: None,
         method,
         params: vec![stream_name],
     };
-    let json = serde_json::to_string(&subscrib_stream).unwrap();
+    let json = serde_json::to_string(&subscrib_stream)?;
     println!("{}", json);
+    Ok(())
 }
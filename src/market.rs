@@ -0,0 +1,278 @@
+use crate::error::ParseError;
+use crate::subscrib_stream::Symbol;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{OnceLock, RwLock};
+
+static REGISTRY: OnceLock<RwLock<MarketRegistry>> = OnceLock::new();
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MarketType {
+    #[serde(rename = "SPOT")]
+    Spot,
+    #[serde(rename = "PERP")]
+    Perp,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Market {
+    pub symbol: Symbol,
+    pub base_asset: String,
+    pub quote_asset: String,
+    pub tick_size: Decimal,
+    pub step_size: Decimal,
+    pub market_type: MarketType,
+}
+
+impl Market {
+    pub fn spot(
+        base_asset: &str,
+        quote_asset: &str,
+        tick_size: Decimal,
+        step_size: Decimal,
+    ) -> Self {
+        Self {
+            symbol: Symbol::new(format!("{}_{}", base_asset, quote_asset)),
+            base_asset: base_asset.to_string(),
+            quote_asset: quote_asset.to_string(),
+            tick_size,
+            step_size,
+            market_type: MarketType::Spot,
+        }
+    }
+
+    pub fn perp(
+        base_asset: &str,
+        quote_asset: &str,
+        tick_size: Decimal,
+        step_size: Decimal,
+    ) -> Self {
+        Self {
+            symbol: Symbol::new(format!("{}_{}_PERP", base_asset, quote_asset)),
+            base_asset: base_asset.to_string(),
+            quote_asset: quote_asset.to_string(),
+            tick_size,
+            step_size,
+            market_type: MarketType::Perp,
+        }
+    }
+
+    // Number of decimals prices are quoted with, e.g. 2 for a tick size of 0.01.
+    pub fn price_precision(&self) -> u32 {
+        self.tick_size.normalize().scale()
+    }
+
+    pub fn quantity_precision(&self) -> u32 {
+        self.step_size.normalize().scale()
+    }
+
+    // The price of `ticks` ticks, quoted with the market's precision.
+    pub fn price_at(&self, ticks: i64) -> Decimal {
+        with_scale(
+            Decimal::from(ticks) * self.tick_size,
+            self.price_precision(),
+        )
+    }
+
+    pub fn quantity_at(&self, steps: i64) -> Decimal {
+        with_scale(
+            Decimal::from(steps) * self.step_size,
+            self.quantity_precision(),
+        )
+    }
+
+    // Rounds to the nearest tick.
+    pub fn round_price(&self, price: Decimal) -> Decimal {
+        with_scale(round_to(price, self.tick_size), self.price_precision())
+    }
+
+    // Rounds down to a whole number of steps, so a quantity never grows past what was asked for.
+    pub fn round_quantity(&self, quantity: Decimal) -> Decimal {
+        let steps = (quantity / self.step_size).trunc();
+        with_scale(steps * self.step_size, self.quantity_precision())
+    }
+}
+
+fn round_to(value: Decimal, increment: Decimal) -> Decimal {
+    (value / increment).round() * increment
+}
+
+fn with_scale(mut value: Decimal, scale: u32) -> Decimal {
+    value.rescale(scale);
+    value
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MarketRegistry {
+    markets: BTreeMap<Symbol, Market>,
+}
+
+impl MarketRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The markets the registry falls back to when nothing else has been installed.
+    pub fn with_defaults() -> Self {
+        Self::new()
+            .with_market(Market::spot(
+                "SOL",
+                "USD",
+                Decimal::new(1, 2),
+                Decimal::new(1, 3),
+            ))
+            .with_market(Market::spot(
+                "SOL",
+                "USDC",
+                Decimal::new(1, 2),
+                Decimal::new(1, 2),
+            ))
+            .with_market(Market::spot(
+                "BTC",
+                "USDC",
+                Decimal::new(1, 1),
+                Decimal::new(1, 5),
+            ))
+            .with_market(Market::spot(
+                "ETH",
+                "USDC",
+                Decimal::new(1, 2),
+                Decimal::new(1, 4),
+            ))
+            .with_market(Market::perp(
+                "SOL",
+                "USDC",
+                Decimal::new(1, 2),
+                Decimal::new(1, 2),
+            ))
+            .with_market(Market::perp(
+                "BTC",
+                "USDC",
+                Decimal::new(1, 1),
+                Decimal::new(1, 5),
+            ))
+            .with_market(Market::perp(
+                "ETH",
+                "USDC",
+                Decimal::new(1, 2),
+                Decimal::new(1, 4),
+            ))
+    }
+
+    // Reads a JSON array of markets, e.g.
+    // [{"symbol": "SOL_USDC", "baseAsset": "SOL", "quoteAsset": "USDC",
+    //   "tickSize": "0.01", "stepSize": "0.01", "marketType": "SPOT"}]
+    pub fn from_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        let markets: Vec<Market> = serde_json::from_str(&content)?;
+        Ok(markets.into_iter().fold(Self::new(), Self::with_market))
+    }
+
+    pub fn with_market(mut self, market: Market) -> Self {
+        self.insert(market);
+        self
+    }
+
+    pub fn insert(&mut self, market: Market) {
+        self.markets.insert(market.symbol.clone(), market);
+    }
+
+    pub fn get(&self, symbol: &str) -> Option<&Market> {
+        self.markets.get(symbol)
+    }
+
+    pub fn markets(&self) -> impl Iterator<Item = &Market> {
+        self.markets.values()
+    }
+
+    pub fn symbol(&self, symbol: &str) -> Result<Symbol, ParseError> {
+        self.get(symbol)
+            .map(|market| market.symbol.clone())
+            .ok_or_else(|| ParseError::UnknownSymbol(symbol.to_string()))
+    }
+
+    // The process-wide registry used by `FromStr` and serde for `Symbol` and `StreamName`.
+    pub fn global() -> &'static RwLock<MarketRegistry> {
+        REGISTRY.get_or_init(|| RwLock::new(Self::with_defaults()))
+    }
+
+    pub fn install(self) {
+        *Self::global().write().unwrap() = self;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subscrib_stream::StreamName;
+    use std::str::FromStr;
+
+    fn decimal(value: &str) -> Decimal {
+        Decimal::from_str(value).unwrap()
+    }
+
+    fn quoted(value: Decimal) -> String {
+        serde_json::to_string(&value).unwrap()
+    }
+
+    #[test]
+    fn rounds_prices_to_the_nearest_tick() {
+        let market = Market::spot("SOL", "USDC", decimal("0.05"), decimal("0.01"));
+        assert_eq!(market.round_price(decimal("18.6749")), decimal("18.65"));
+        assert_eq!(market.round_price(decimal("18.676")), decimal("18.70"));
+        assert_eq!(market.round_price(decimal("18.7")), decimal("18.70"));
+    }
+
+    #[test]
+    fn truncates_quantities_to_whole_steps() {
+        let market = Market::spot("BTC", "USDC", decimal("0.1"), decimal("0.001"));
+        assert_eq!(market.round_quantity(decimal("0.12399")), decimal("0.123"));
+        assert_eq!(market.round_quantity(decimal("0.0009")), decimal("0"));
+        assert_eq!(market.quantity_at(1500), decimal("1.5"));
+    }
+
+    #[test]
+    fn quotes_with_the_precision_of_the_increment() {
+        let market = Market::spot("SOL", "USDC", decimal("0.0001"), decimal("0.01"));
+        assert_eq!(quoted(market.price_at(100)), "\"0.0100\"");
+        assert_eq!(quoted(market.round_price(decimal("0.01"))), "\"0.0100\"");
+        assert_eq!(quoted(market.quantity_at(200)), "\"2.00\"");
+        // Trailing zeros in the increment itself do not add decimals.
+        let market = Market::spot("SOL", "USDC", decimal("0.0100"), decimal("1"));
+        assert_eq!(market.price_precision(), 2);
+        assert_eq!(quoted(market.price_at(1)), "\"0.01\"");
+        assert_eq!(quoted(market.round_quantity(decimal("2.7"))), "\"2\"");
+    }
+
+    #[test]
+    fn loaded_registry_decides_which_streams_parse() {
+        let path =
+            std::env::temp_dir().join(format!("backpack-markets-{}.json", std::process::id()));
+        let markets = r#"[
+            {"symbol": "JUP_USDC", "baseAsset": "JUP", "quoteAsset": "USDC",
+             "tickSize": "0.0001", "stepSize": "1", "marketType": "SPOT"},
+            {"symbol": "JUP_USDC_PERP", "baseAsset": "JUP", "quoteAsset": "USDC",
+             "tickSize": "0.0001", "stepSize": "1", "marketType": "PERP"}
+        ]"#;
+        std::fs::write(&path, markets).unwrap();
+        let registry = MarketRegistry::from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let market = registry.get("JUP_USDC_PERP").unwrap();
+        assert_eq!(market.market_type, MarketType::Perp);
+        assert_eq!(market.tick_size, decimal("0.0001"));
+        assert_eq!(registry.markets().count(), 2);
+
+        let stream = StreamName::parse_with("depth.JUP_USDC", &registry).unwrap();
+        assert_eq!(stream.to_string(), "depth.JUP_USDC");
+        assert!(StreamName::parse_with("kline.1m.JUP_USDC_PERP", &registry).is_ok());
+        // Markets only in the defaults are unknown to a loaded registry.
+        assert_eq!(
+            StreamName::parse_with("trade.SOL_USDC", &registry),
+            Err(ParseError::UnknownSymbol("SOL_USDC".into()))
+        );
+    }
+}
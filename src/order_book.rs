@@ -0,0 +1,232 @@
+use crate::error::BookError;
+use crate::event_type::DepthStream;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// The full book at `last_update_id`, as served by `GET /api/v1/depth`. Asks run from the
+// best price up and bids from the best price down.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct DepthSnapshot {
+    pub asks: Vec<(Decimal, Decimal)>,
+    pub bids: Vec<(Decimal, Decimal)>,
+    pub last_update_id: u64,
+    pub timestamp: u64,
+}
+
+// A local copy of one market's book, kept up to date from depth events. Each event must
+// continue from the last one applied; a gap or a crossed book means the copy can no longer
+// be trusted and has to be rebuilt.
+#[derive(Debug, Clone, Default)]
+pub struct OrderBook {
+    bids: BTreeMap<Decimal, Decimal>,
+    asks: BTreeMap<Decimal, Decimal>,
+    last_update_id: Option<u64>,
+}
+
+impl OrderBook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // A book seeded from a snapshot. Depth events up to its `last_update_id` are then skipped
+    // and the first one applied has to straddle it.
+    pub fn from_snapshot(snapshot: &DepthSnapshot) -> Self {
+        Self {
+            bids: snapshot.bids.iter().copied().collect(),
+            asks: snapshot.asks.iter().copied().collect(),
+            last_update_id: Some(snapshot.last_update_id),
+        }
+    }
+
+    pub fn last_update_id(&self) -> Option<u64> {
+        self.last_update_id
+    }
+
+    // Applies the level updates in `depth`. Returns false for an event the book has already
+    // seen in full.
+    pub fn apply(&mut self, depth: &DepthStream) -> Result<bool, BookError> {
+        if let Some(last_update_id) = self.last_update_id {
+            if depth.final_update_id() <= last_update_id {
+                return Ok(false);
+            }
+            if depth.first_update_id() > last_update_id + 1 {
+                return Err(BookError::Gap {
+                    expected: last_update_id + 1,
+                    first: depth.first_update_id(),
+                });
+            }
+        }
+        for &(price, quantity) in depth.bids() {
+            update_level(&mut self.bids, price, quantity);
+        }
+        for &(price, quantity) in depth.asks() {
+            update_level(&mut self.asks, price, quantity);
+        }
+        self.last_update_id = Some(depth.final_update_id());
+        match (self.best_bid(), self.best_ask()) {
+            (Some((bid, _)), Some((ask, _))) if bid >= ask => Err(BookError::Crossed { bid, ask }),
+            _ => Ok(true),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bids.is_empty() && self.asks.is_empty()
+    }
+
+    pub fn best_bid(&self) -> Option<(Decimal, Decimal)> {
+        self.bids.iter().next_back().map(|(&p, &q)| (p, q))
+    }
+
+    pub fn best_ask(&self) -> Option<(Decimal, Decimal)> {
+        self.asks.iter().next().map(|(&p, &q)| (p, q))
+    }
+
+    pub fn mid_price(&self) -> Option<Decimal> {
+        let ((bid, _), (ask, _)) = (self.best_bid()?, self.best_ask()?);
+        Some((bid + ask) / Decimal::TWO)
+    }
+
+    pub fn spread(&self) -> Option<Decimal> {
+        let ((bid, _), (ask, _)) = (self.best_bid()?, self.best_ask()?);
+        Some(ask - bid)
+    }
+
+    // Bids from the best price down.
+    pub fn bids(&self) -> impl Iterator<Item = (Decimal, Decimal)> + '_ {
+        self.bids.iter().rev().map(|(&p, &q)| (p, q))
+    }
+
+    // Asks from the best price up.
+    pub fn asks(&self) -> impl Iterator<Item = (Decimal, Decimal)> + '_ {
+        self.asks.iter().map(|(&p, &q)| (p, q))
+    }
+
+    pub fn top_bids(&self, levels: usize) -> Vec<(Decimal, Decimal)> {
+        self.bids().take(levels).collect()
+    }
+
+    pub fn top_asks(&self, levels: usize) -> Vec<(Decimal, Decimal)> {
+        self.asks().take(levels).collect()
+    }
+
+    // The first `levels` bids with the total quantity available down to each price.
+    pub fn cumulative_bids(&self, levels: usize) -> Vec<(Decimal, Decimal)> {
+        cumulative(self.bids().take(levels))
+    }
+
+    pub fn cumulative_asks(&self, levels: usize) -> Vec<(Decimal, Decimal)> {
+        cumulative(self.asks().take(levels))
+    }
+}
+
+fn update_level(side: &mut BTreeMap<Decimal, Decimal>, price: Decimal, quantity: Decimal) {
+    match quantity.is_zero() {
+        true => side.remove(&price),
+        false => side.insert(price, quantity),
+    };
+}
+
+fn cumulative(levels: impl Iterator<Item = (Decimal, Decimal)>) -> Vec<(Decimal, Decimal)> {
+    levels
+        .scan(Decimal::ZERO, |total, (price, quantity)| {
+            *total += quantity;
+            Some((price, *total))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn depth(first: u64, last: u64, bids: &[(&str, &str)], asks: &[(&str, &str)]) -> DepthStream {
+        serde_json::from_value(serde_json::json!({
+            "e": "depth", "E": 1, "s": "SOL_USDC", "a": asks, "b": bids,
+            "U": first, "u": last, "T": 1
+        }))
+        .unwrap()
+    }
+
+    fn decimal(value: &str) -> Decimal {
+        value.parse().unwrap()
+    }
+
+    fn snapshot() -> OrderBook {
+        OrderBook::from_snapshot(&DepthSnapshot {
+            asks: vec![
+                (decimal("100.02"), decimal("1")),
+                (decimal("100.03"), decimal("2")),
+            ],
+            bids: vec![
+                (decimal("100.00"), decimal("3")),
+                (decimal("99.99"), decimal("4")),
+            ],
+            last_update_id: 10,
+            timestamp: 0,
+        })
+    }
+
+    #[test]
+    fn applies_updates_continuing_from_the_snapshot() {
+        let mut book = snapshot();
+        // Straddles the snapshot: 9 and 10 are already in it.
+        let update = depth(9, 12, &[("100.01", "5"), ("99.99", "0")], &[]);
+        assert_eq!(book.apply(&update), Ok(true));
+        assert_eq!(book.last_update_id(), Some(12));
+        assert_eq!(book.best_bid(), Some((decimal("100.01"), decimal("5"))));
+        assert_eq!(book.top_bids(3).len(), 2);
+        assert_eq!(book.spread(), Some(decimal("0.01")));
+    }
+
+    #[test]
+    fn skips_events_already_in_the_book() {
+        let mut book = snapshot();
+        let stale = depth(8, 10, &[("100.01", "5")], &[]);
+        assert_eq!(book.apply(&stale), Ok(false));
+        assert_eq!(book.last_update_id(), Some(10));
+        assert_eq!(book.best_bid(), Some((decimal("100.00"), decimal("3"))));
+    }
+
+    #[test]
+    fn reports_a_gap() {
+        let mut book = snapshot();
+        let ahead = depth(12, 13, &[], &[("100.02", "0")]);
+        assert_eq!(
+            book.apply(&ahead),
+            Err(BookError::Gap {
+                expected: 11,
+                first: 12
+            })
+        );
+        assert_eq!(book.last_update_id(), Some(10));
+    }
+
+    #[test]
+    fn reports_a_crossed_book() {
+        let mut book = snapshot();
+        let crossing = depth(11, 11, &[("100.02", "1")], &[]);
+        assert_eq!(
+            book.apply(&crossing),
+            Err(BookError::Crossed {
+                bid: decimal("100.02"),
+                ask: decimal("100.02")
+            })
+        );
+    }
+
+    #[test]
+    fn an_empty_book_takes_any_first_event() {
+        let mut book = OrderBook::new();
+        assert_eq!(book.apply(&depth(500, 501, &[("1", "1")], &[])), Ok(true));
+        assert_eq!(book.last_update_id(), Some(501));
+        book.clear();
+        assert!(book.is_empty());
+        assert_eq!(book.last_update_id(), None);
+    }
+}
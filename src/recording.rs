@@ -0,0 +1,158 @@
+use crate::envelope::StreamEnvelope;
+use crate::error::SinkError;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+// One line of a recording, e.g.
+// `{"receivedAt": 1718000000123456, "stream": "depth.SOL_USDC", "data": {...}}`.
+// `receivedAt` is the local clock in Unix microseconds when the event was received, not
+// the exchange's `E`, so the gaps between lines are the gaps the recorder saw.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordedEvent<T = Value> {
+    pub received_at: u64,
+    pub stream: String,
+    pub data: T,
+}
+
+impl<'a, T> RecordedEvent<&'a T> {
+    pub fn new(envelope: &'a StreamEnvelope<T>, received_at: u64) -> Self {
+        Self {
+            received_at,
+            stream: envelope.stream.clone(),
+            data: &envelope.data,
+        }
+    }
+}
+
+impl<T> RecordedEvent<T> {
+    pub fn into_envelope(self) -> StreamEnvelope<T> {
+        StreamEnvelope::new(self.stream, self.data)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    pub fn extension(self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some("gz"),
+            Compression::Zstd => Some("zst"),
+        }
+    }
+
+    // Guessed from the file extension, anything other than `.gz` or `.zst` is plain text.
+    pub fn of(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("zst") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = SinkError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "none" => Ok(Compression::None),
+            "gzip" | "gz" => Ok(Compression::Gzip),
+            "zstd" | "zst" => Ok(Compression::Zstd),
+            _ => Err(SinkError::UnknownCompression(name.to_string())),
+        }
+    }
+}
+
+// Compresses a finished recording next to it, e.g. `a.ndjson` into `a.ndjson.gz`, and
+// removes the original once the compressed copy is complete. Blocking.
+pub fn compress(path: &Path, compression: Compression) -> io::Result<PathBuf> {
+    let Some(extension) = compression.extension() else {
+        return Ok(path.to_path_buf());
+    };
+    let mut target = path.as_os_str().to_owned();
+    target.push(".");
+    target.push(extension);
+    let target = PathBuf::from(target);
+
+    let mut input = BufReader::new(File::open(path)?);
+    let output = File::create(&target)?;
+    match compression {
+        Compression::None => unreachable!(),
+        Compression::Gzip => {
+            let mut encoder = flate2::write::GzEncoder::new(output, flate2::Compression::default());
+            io::copy(&mut input, &mut encoder)?;
+            encoder.finish()?.sync_all()?;
+        }
+        Compression::Zstd => {
+            let mut encoder = zstd::Encoder::new(output, 0)?;
+            io::copy(&mut input, &mut encoder)?;
+            encoder.finish()?.sync_all()?;
+        }
+    }
+    fs::remove_file(path)?;
+    Ok(target)
+}
+
+// Opens a recording for reading line by line, decompressing `.gz` and `.zst` files.
+pub fn open_recording(path: &Path) -> io::Result<Box<dyn BufRead + Send>> {
+    let file = File::open(path)?;
+    Ok(match Compression::of(path) {
+        Compression::None => Box::new(BufReader::new(file)),
+        Compression::Gzip => Box::new(BufReader::new(flate2::read::MultiGzDecoder::new(file))),
+        Compression::Zstd => Box::new(BufReader::new(zstd::Decoder::new(file)?)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn compressed_recording_replaces_the_original() {
+        let dir = std::env::temp_dir().join(format!("backpack-compress-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let content = "{\"receivedAt\":1,\"stream\":\"depth.SOL_USDC\",\"data\":{}}\n".repeat(100);
+        for compression in [Compression::Gzip, Compression::Zstd] {
+            let path = dir.join("segment.ndjson");
+            fs::write(&path, &content).unwrap();
+            let compressed = compress(&path, compression).unwrap();
+            assert!(!path.exists());
+            assert_eq!(Compression::of(&compressed), compression);
+            let file = File::open(&compressed).unwrap();
+            let mut decompressed = String::new();
+            match compression {
+                Compression::Gzip => flate2::read::GzDecoder::new(file)
+                    .read_to_string(&mut decompressed)
+                    .unwrap(),
+                _ => zstd::Decoder::new(file)
+                    .unwrap()
+                    .read_to_string(&mut decompressed)
+                    .unwrap(),
+            };
+            assert_eq!(decompressed, content);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn recorded_line_carries_the_stream_and_receive_time() {
+        let envelope = StreamEnvelope::new("trade.SOL_USDC", serde_json::json!({"t": 1}));
+        let line = serde_json::to_value(RecordedEvent::new(&envelope, 1718000000123456)).unwrap();
+        assert_eq!(line["stream"], "trade.SOL_USDC");
+        assert_eq!(line["data"]["t"], 1);
+        assert_eq!(line["receivedAt"], 1718000000123456u64);
+    }
+}
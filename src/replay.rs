@@ -0,0 +1,135 @@
+use crate::recording::{open_recording, RecordedEvent};
+use serde::{Deserialize, Serialize};
+use std::io::BufRead;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+// Recorded events read ahead of the one being waited for.
+const READ_AHEAD: usize = 1000;
+
+// What `backpack_server` plays instead of simulating, e.g.
+// [replay]
+// files = ["./recordings/backpack-1718000000000-0001.ndjson.gz"]
+// speed = 10.0
+// loop = true
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct ReplaySettings {
+    // Played one after another, e.g. the segments of a rotated recording in order.
+    pub files: Vec<PathBuf>,
+    // How many times faster than recorded events are played.
+    pub speed: f64,
+    // Ignores the recorded timing and plays events as fast as subscribers take them.
+    pub max_speed: bool,
+    #[serde(rename = "loop")]
+    pub looped: bool,
+    // Skips events received before this Unix time in microseconds.
+    pub from_micros: Option<u64>,
+}
+
+impl Default for ReplaySettings {
+    fn default() -> Self {
+        Self {
+            files: Vec::new(),
+            speed: 1.0,
+            max_speed: false,
+            looped: false,
+            from_micros: None,
+        }
+    }
+}
+
+// Events of a recording, handed out with the gaps between them as they were received,
+// scaled by the speed. Every loop starts over from the time of its first event.
+pub struct Replay {
+    events: mpsc::Receiver<(u64, RecordedEvent)>,
+    speed: Option<f64>,
+    // The pass, the `receivedAt` of its first event and when that was handed out.
+    anchor: Option<(u64, u64, Instant)>,
+}
+
+impl Replay {
+    // Reads the files on a blocking thread, so this has to be called within a Tokio runtime.
+    pub fn start(settings: ReplaySettings) -> Self {
+        let (sender, events) = mpsc::channel(READ_AHEAD);
+        let speed = (!settings.max_speed).then_some(settings.speed);
+        tokio::task::spawn_blocking(move || read_recordings(&settings, sender));
+        Self {
+            events,
+            speed,
+            anchor: None,
+        }
+    }
+
+    pub fn is_paced(&self) -> bool {
+        self.speed.is_some()
+    }
+
+    // None once every file has been played, never when looping.
+    pub async fn next(&mut self) -> Option<RecordedEvent> {
+        let (pass, event) = self.events.recv().await?;
+        let Some(speed) = self.speed else {
+            return Some(event);
+        };
+        let (first, started) = match self.anchor {
+            Some((anchored, first, started)) if anchored == pass => (first, started),
+            _ => {
+                self.anchor = Some((pass, event.received_at, Instant::now()));
+                return Some(event);
+            }
+        };
+        // Out of order lines are played right away rather than held back.
+        let offset = Duration::from_micros(event.received_at.saturating_sub(first));
+        tokio::time::sleep_until((started + offset.div_f64(speed)).into()).await;
+        Some(event)
+    }
+}
+
+fn read_recordings(settings: &ReplaySettings, events: mpsc::Sender<(u64, RecordedEvent)>) {
+    for pass in 0.. {
+        let mut played = 0usize;
+        for path in &settings.files {
+            let recording = match open_recording(path) {
+                Ok(recording) => recording,
+                Err(e) => {
+                    warn!("Failed to open {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            info!("Replaying: {}", path.display());
+            for (number, line) in recording.lines().enumerate() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(e) => {
+                        warn!("Failed to read {}: {}", path.display(), e);
+                        break;
+                    }
+                };
+                let event: RecordedEvent = match serde_json::from_str(&line) {
+                    Ok(event) => event,
+                    Err(e) => {
+                        warn!("Skipping line {} of {}: {}", number + 1, path.display(), e);
+                        continue;
+                    }
+                };
+                if settings
+                    .from_micros
+                    .is_some_and(|from| event.received_at < from)
+                {
+                    continue;
+                }
+                played += 1;
+                if events.blocking_send((pass, event)).is_err() {
+                    return;
+                }
+            }
+        }
+        info!("Replayed {} events", played);
+        // Nothing to play would loop forever without ever handing out an event.
+        if !settings.looped || played == 0 {
+            return;
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use crate::subscrib_stream::StreamName;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    MalformedRequest,
+    UnknownMethod,
+    InvalidStream,
+    AlreadySubscribed,
+    NotSubscribed,
+    TooManySubscriptions,
+    InvalidSymbol,
+    NotFound,
+    ReplayFinished,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ResponseError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+// The reply to a SUBSCRIBE or UNSUBSCRIBE request, carrying the request `id` if it had one.
+// On success `result` lists the streams the request applied to, otherwise `error` says why
+// nothing was applied. Stream names are kept as sent, like in `StreamEnvelope`, so replies
+// about markets missing from the receiver's registry still decode.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ResponseError>,
+}
+
+impl Response {
+    pub fn success(id: Option<u64>, streams: Vec<StreamName>) -> Self {
+        Self {
+            id,
+            result: Some(streams.iter().map(StreamName::to_string).collect()),
+            error: None,
+        }
+    }
+
+    pub fn error(id: Option<u64>, code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            id,
+            result: None,
+            error: Some(ResponseError {
+                code,
+                message: message.into(),
+            }),
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn response_for_unknown_market_decodes() {
+        let response: Response =
+            serde_json::from_str(r#"{"id":1,"result":["depth.JUP_USDC"]}"#).unwrap();
+        assert_eq!(response.result, Some(vec!["depth.JUP_USDC".to_string()]));
+        assert!(response.is_success());
+    }
+}
@@ -0,0 +1,670 @@
+use crate::clock::Clock;
+use crate::event_type::EventType;
+use crate::market::Market;
+use crate::order_book::DepthSnapshot;
+use crate::subscrib_stream::{StreamName, Symbol};
+use crate::{parse_stream_name, UpdataStream};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::path::Path;
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::Message;
+
+pub mod price_model;
+
+pub use price_model::{PriceModel, PriceModelConfig};
+
+const BOOK_LEVELS: i64 = 20;
+const TRADE_TAPE_LEN: usize = 1000;
+const BOOK_UPDATE_LOG_LEN: usize = 1000;
+
+#[derive(Debug, Clone)]
+pub struct SimTrade {
+    pub id: u64,
+    pub time: u64,
+    pub price: Decimal,
+    pub quantity: Decimal,
+    pub buyer_order_id: u64,
+    pub seller_order_id: u64,
+    pub is_buyer_the_maker: bool,
+}
+
+// The levels changed by one book update, a zero quantity meaning the level was removed.
+#[derive(Debug, Clone)]
+pub struct BookUpdate {
+    pub update_id: u64,
+    pub bids: Vec<(Decimal, Decimal)>,
+    pub asks: Vec<(Decimal, Decimal)>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct SessionStats {
+    pub first_price: Option<Decimal>,
+    pub last_price: Option<Decimal>,
+    pub high_price: Option<Decimal>,
+    pub low_price: Option<Decimal>,
+    pub base_asset_volume: Decimal,
+    pub quote_asset_volume: Decimal,
+    pub number_of_trades: u64,
+}
+
+impl SessionStats {
+    fn record(&mut self, trade: &SimTrade) {
+        self.first_price.get_or_insert(trade.price);
+        self.last_price = Some(trade.price);
+        self.high_price = Some(self.high_price.map_or(trade.price, |p| p.max(trade.price)));
+        self.low_price = Some(self.low_price.map_or(trade.price, |p| p.min(trade.price)));
+        self.base_asset_volume += trade.quantity;
+        self.quote_asset_volume += trade.price * trade.quantity;
+        self.number_of_trades += 1;
+    }
+}
+
+// Simulated state of one market: a mid price, an order book around it and a trade tape.
+// Every event stream for the market is derived from this, so the feeds agree with each other.
+// Book prices are kept in ticks and quantities in steps of the market.
+pub struct MarketSimulator {
+    market: Market,
+    time: u64,
+    engine_lag: u64,
+    mid_price: f64,
+    price_model: Box<dyn PriceModel>,
+    bids: BTreeMap<i64, i64>,
+    asks: BTreeMap<i64, i64>,
+    update_id: u64,
+    book_updates: VecDeque<BookUpdate>,
+    trades: VecDeque<SimTrade>,
+    next_order_id: u64,
+    stats: SessionStats,
+}
+
+impl MarketSimulator {
+    pub fn new(market: Market, initial_price: f64, price_model: Box<dyn PriceModel>) -> Self {
+        Self {
+            market,
+            time: 0,
+            engine_lag: 0,
+            mid_price: initial_price,
+            price_model,
+            bids: BTreeMap::new(),
+            asks: BTreeMap::new(),
+            update_id: 0,
+            book_updates: VecDeque::new(),
+            trades: VecDeque::new(),
+            next_order_id: 111_063_070_525_358_080,
+            stats: SessionStats::default(),
+        }
+    }
+
+    pub fn market(&self) -> &Market {
+        &self.market
+    }
+
+    // Event time of the last step.
+    pub fn time(&self) -> u64 {
+        self.time
+    }
+
+    // Matching engine time of the last step, which trails the event time.
+    pub fn engine_time(&self) -> u64 {
+        self.time.saturating_sub(self.engine_lag)
+    }
+
+    pub fn with_engine_lag(mut self, engine_lag: u64) -> Self {
+        self.engine_lag = engine_lag;
+        self
+    }
+
+    pub fn mid_price(&self) -> f64 {
+        self.mid_price
+    }
+
+    pub fn update_id(&self) -> u64 {
+        self.update_id
+    }
+
+    pub fn stats(&self) -> &SessionStats {
+        &self.stats
+    }
+
+    pub fn last_price(&self) -> Decimal {
+        self.stats
+            .last_price
+            .unwrap_or_else(|| self.market.price_at(self.mid_ticks()))
+    }
+
+    pub fn last_trade(&self) -> Option<&SimTrade> {
+        self.trades.back()
+    }
+
+    // Book updates after `update_id`, oldest first, as far back as the log goes.
+    pub fn book_updates_since(&self, update_id: u64) -> impl Iterator<Item = &BookUpdate> {
+        self.book_updates
+            .iter()
+            .filter(move |update| update.update_id > update_id)
+    }
+
+    pub fn trades_since(&self, trade_id: u64) -> impl Iterator<Item = &SimTrade> {
+        self.trades.iter().filter(move |trade| trade.id > trade_id)
+    }
+
+    pub fn best_bid(&self) -> Option<(Decimal, Decimal)> {
+        self.bids
+            .iter()
+            .next_back()
+            .map(|(&price, &quantity)| self.level(price, quantity))
+    }
+
+    pub fn best_ask(&self) -> Option<(Decimal, Decimal)> {
+        self.asks
+            .iter()
+            .next()
+            .map(|(&price, &quantity)| self.level(price, quantity))
+    }
+
+    // Bids from the best price down.
+    pub fn bids(&self) -> impl Iterator<Item = (Decimal, Decimal)> + '_ {
+        self.bids
+            .iter()
+            .rev()
+            .map(|(&price, &quantity)| self.level(price, quantity))
+    }
+
+    // Asks from the best price up.
+    pub fn asks(&self) -> impl Iterator<Item = (Decimal, Decimal)> + '_ {
+        self.asks
+            .iter()
+            .map(|(&price, &quantity)| self.level(price, quantity))
+    }
+
+    pub fn depth_snapshot(&self) -> DepthSnapshot {
+        DepthSnapshot {
+            asks: self.asks().collect(),
+            bids: self.bids().collect(),
+            last_update_id: self.update_id,
+            timestamp: self.time,
+        }
+    }
+
+    // Moves the market on by `dt` to `time`. The price model is stepped by `dt` rather than
+    // by the gap between event times, so a seeded market follows the same path however
+    // unevenly it happens to be stepped.
+    pub fn step<R: Rng>(&mut self, time: u64, dt: Duration, rng: &mut R) {
+        let dt = dt.as_secs_f64();
+        self.time = time;
+        if !self.bids.is_empty() && rng.gen_bool(0.7) {
+            self.match_market_order(rng);
+        }
+        let tick_size = increment(self.market.tick_size);
+        self.mid_price = self
+            .price_model
+            .next_price(self.mid_price, dt, rng)
+            .max(tick_size * 3.0);
+        self.refresh_book(rng);
+    }
+
+    fn level(&self, price: i64, quantity: i64) -> (Decimal, Decimal) {
+        (
+            self.market.price_at(price),
+            self.market.quantity_at(quantity),
+        )
+    }
+
+    // The mid price rounded to whole ticks.
+    fn mid_ticks(&self) -> i64 {
+        (self.mid_price / increment(self.market.tick_size)).round() as i64
+    }
+
+    // A taker order that trades against the best level on one side of the book.
+    fn match_market_order<R: Rng>(&mut self, rng: &mut R) {
+        let is_buy = rng.gen_bool(0.5);
+        let book = if is_buy {
+            &mut self.asks
+        } else {
+            &mut self.bids
+        };
+        let mut best = match is_buy {
+            true => book.first_entry(),
+            false => book.last_entry(),
+        }
+        .unwrap();
+        let price = *best.key();
+        let quantity = rng.gen_range(1..=*best.get());
+        *best.get_mut() -= quantity;
+        let level = BTreeMap::from([(price, *best.get())]);
+        if *best.get() == 0 {
+            best.remove();
+        }
+        let taker_order_id = self.next_order_id;
+        let maker_order_id = self.next_order_id + rng.gen_range(1..1000);
+        self.next_order_id = maker_order_id + 1;
+        let (buyer_order_id, seller_order_id) = match is_buy {
+            true => (taker_order_id, maker_order_id),
+            false => (maker_order_id, taker_order_id),
+        };
+        let (price, quantity) = self.level(price, quantity);
+        let trade = SimTrade {
+            id: self.last_trade().map_or(1, |trade| trade.id + 1),
+            time: self.engine_time(),
+            price,
+            quantity,
+            buyer_order_id,
+            seller_order_id,
+            is_buyer_the_maker: !is_buy,
+        };
+        self.stats.record(&trade);
+        self.trades.push_back(trade);
+        if self.trades.len() > TRADE_TAPE_LEN {
+            self.trades.pop_front();
+        }
+        match is_buy {
+            true => self.record_book_update(&BTreeMap::new(), &level),
+            false => self.record_book_update(&level, &BTreeMap::new()),
+        }
+    }
+
+    // Re-centres the book on the mid price: levels that would cross are pulled, missing
+    // levels within `BOOK_LEVELS` ticks of the touch are filled and resting ones drift.
+    fn refresh_book<R: Rng>(&mut self, rng: &mut R) {
+        let mid = self.mid_ticks();
+        let half_spread = rng.gen_range(1..=2);
+        let best_bid = mid - half_spread;
+        let best_ask = mid + half_spread;
+        let (bids_before, asks_before) = (self.bids.clone(), self.asks.clone());
+
+        let stale = |price: &i64| *price > best_bid || *price <= best_bid - BOOK_LEVELS;
+        self.bids.retain(|price, _| !stale(price));
+        let stale = |price: &i64| *price < best_ask || *price >= best_ask + BOOK_LEVELS;
+        self.asks.retain(|price, _| !stale(price));
+
+        for offset in 0..BOOK_LEVELS {
+            for (book, price) in [
+                (&mut self.bids, best_bid - offset),
+                (&mut self.asks, best_ask + offset),
+            ] {
+                let quantity = random_quantity(&self.market, price, rng);
+                match book.get_mut(&price) {
+                    // The touch is always quoted, deeper levels may be empty.
+                    None if offset == 0 || rng.gen_bool(0.3) => {
+                        book.insert(price, quantity);
+                    }
+                    Some(resting) if rng.gen_bool(0.1) => *resting = quantity,
+                    _ => {}
+                }
+            }
+        }
+        let bids = changed_levels(&bids_before, &self.bids);
+        let asks = changed_levels(&asks_before, &self.asks);
+        self.record_book_update(&bids, &asks);
+    }
+
+    // Logs the changed levels, given in ticks and steps, under a new update id.
+    fn record_book_update(&mut self, bids: &BTreeMap<i64, i64>, asks: &BTreeMap<i64, i64>) {
+        if bids.is_empty() && asks.is_empty() {
+            return;
+        }
+        self.update_id += 1;
+        let levels = |side: &BTreeMap<i64, i64>| {
+            side.iter()
+                .map(|(&price, &quantity)| self.level(price, quantity))
+                .collect()
+        };
+        let update = BookUpdate {
+            update_id: self.update_id,
+            bids: levels(bids),
+            asks: levels(asks),
+        };
+        self.book_updates.push_back(update);
+        if self.book_updates.len() > BOOK_UPDATE_LOG_LEN {
+            self.book_updates.pop_front();
+        }
+    }
+}
+
+// Levels that differ between two states of one side of the book, removed ones at zero.
+fn changed_levels(before: &BTreeMap<i64, i64>, after: &BTreeMap<i64, i64>) -> BTreeMap<i64, i64> {
+    let removed = before
+        .keys()
+        .filter(|price| !after.contains_key(price))
+        .map(|&price| (price, 0));
+    let changed = after
+        .iter()
+        .filter(|(price, quantity)| before.get(price) != Some(quantity))
+        .map(|(&price, &quantity)| (price, quantity));
+    removed.chain(changed).collect()
+}
+
+// Level sizes between roughly 50 and 2000 in quote currency, whatever the market.
+fn random_quantity<R: Rng>(market: &Market, price: i64, rng: &mut R) -> i64 {
+    let notional = rng.gen_range(50.0..2000.0);
+    let price = price as f64 * increment(market.tick_size);
+    ((notional / price / increment(market.step_size)).round() as i64).max(1)
+}
+
+fn increment(size: Decimal) -> f64 {
+    size.to_f64().unwrap_or(f64::EPSILON)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct SimulationSettings {
+    pub initial_price: f64,
+    #[serde(default)]
+    pub price_model: PriceModelConfig,
+}
+
+impl Default for SimulationSettings {
+    fn default() -> Self {
+        Self {
+            initial_price: 100.0,
+            price_model: PriceModelConfig::default(),
+        }
+    }
+}
+
+// Per-symbol simulation settings, an optional seed, the engine timestamp lag and how often
+// each kind of stream publishes at most, e.g.
+// {"seed": 42, "engineLagMicros": 1000, "publishIntervalMillis": {"ticker": 5000},
+//  "SOL_USDC": {"initialPrice": 165.0, "priceModel": {"model": "gbm", "drift": 0.0, "volatility": 3.0}}}
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulationConfig {
+    #[serde(default)]
+    pub seed: Option<u64>,
+    #[serde(default)]
+    pub engine_lag_micros: u64,
+    // Streams not listed publish on every tick that has news.
+    #[serde(default)]
+    pub publish_interval_millis: BTreeMap<EventType, u64>,
+    #[serde(flatten)]
+    pub markets: BTreeMap<Symbol, SimulationSettings>,
+}
+
+impl SimulationConfig {
+    pub fn from_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    pub fn simulator(&self, market: Market) -> MarketSimulator {
+        let settings = self
+            .markets
+            .get(&market.symbol)
+            .cloned()
+            .unwrap_or_default();
+        MarketSimulator::new(market, settings.initial_price, settings.price_model.build())
+            .with_engine_lag(self.engine_lag_micros)
+    }
+
+    pub fn feed(&self, market: Market) -> MarketFeed {
+        let rng = market_rng(self.seed, &market.symbol);
+        let intervals = self
+            .publish_interval_millis
+            .iter()
+            .map(|(&stream, &millis)| (stream, millis * 1000))
+            .collect();
+        MarketFeed::new(self.simulator(market), rng).with_publish_intervals(intervals)
+    }
+}
+
+// The random source for one market. With a seed every market gets its own reproducible
+// sequence, independent of which other markets are being simulated alongside it.
+pub fn market_rng(seed: Option<u64>, symbol: &Symbol) -> StdRng {
+    match seed {
+        // FNV-1a, so the derived seed is stable across builds and platforms.
+        Some(seed) => StdRng::seed_from_u64(
+            symbol
+                .as_str()
+                .bytes()
+                .fold(seed ^ 0xcbf2_9ce4_8422_2325, |hash, byte| {
+                    (hash ^ u64::from(byte)).wrapping_mul(0x100_0000_01b3)
+                }),
+        ),
+        None => StdRng::from_entropy(),
+    }
+}
+
+struct FeedStream {
+    stream: Box<dyn UpdataStream>,
+    interval_micros: u64,
+    next_publish: u64,
+}
+
+// A simulated market together with the streams subscribed to it.
+pub struct MarketFeed {
+    simulator: MarketSimulator,
+    rng: StdRng,
+    publish_intervals: BTreeMap<EventType, u64>,
+    streams: BTreeMap<String, FeedStream>,
+}
+
+impl MarketFeed {
+    pub fn new(simulator: MarketSimulator, rng: StdRng) -> Self {
+        Self {
+            simulator,
+            rng,
+            publish_intervals: BTreeMap::new(),
+            streams: BTreeMap::new(),
+        }
+    }
+
+    // The least time between two messages of a kind of stream, in microseconds of market time.
+    pub fn with_publish_intervals(mut self, publish_intervals: BTreeMap<EventType, u64>) -> Self {
+        self.publish_intervals = publish_intervals;
+        self
+    }
+
+    pub fn simulator(&self) -> &MarketSimulator {
+        &self.simulator
+    }
+
+    pub fn subscribe(&mut self, stream_name: StreamName) {
+        let interval_micros = self
+            .publish_intervals
+            .get(&stream_name.stream)
+            .copied()
+            .unwrap_or(0);
+        let simulator = &self.simulator;
+        self.streams
+            .entry(stream_name.to_string())
+            .or_insert_with(|| {
+                let mut stream = parse_stream_name(stream_name);
+                stream.start(simulator);
+                FeedStream {
+                    stream,
+                    interval_micros,
+                    next_publish: 0,
+                }
+            });
+    }
+
+    pub fn unsubscribe(&mut self, stream_name: &StreamName) {
+        self.streams.remove(&stream_name.to_string());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.streams.is_empty()
+    }
+
+    // Advances the market by `step` and collects a message from every stream that has news
+    // and is due, keyed by stream name. A held-back depth stream merges the updates in
+    // between into its next message.
+    pub fn tick(&mut self, clock: &dyn Clock, step: Duration) -> Vec<(String, Message)> {
+        let now = clock.now_micros();
+        self.simulator.step(now, step, &mut self.rng);
+        self.streams
+            .iter_mut()
+            .filter(|(_, feed)| feed.next_publish <= now)
+            .filter_map(|(name, feed)| {
+                feed.stream.update(&self.simulator).then(|| {
+                    feed.next_publish = now + feed.interval_micros;
+                    (name.clone(), feed.stream.to_message(name))
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::SimulatedClock;
+    use crate::envelope::StreamEnvelope;
+    use crate::event_type::DepthStream;
+    use crate::market::MarketRegistry;
+    use crate::order_book::OrderBook;
+
+    const STEP: Duration = Duration::from_secs(1);
+
+    fn sol_feed(seed: u64) -> MarketFeed {
+        let market = MarketRegistry::with_defaults()
+            .get("SOL_USDC")
+            .cloned()
+            .unwrap();
+        let config = SimulationConfig {
+            seed: Some(seed),
+            ..SimulationConfig::default()
+        };
+        config.feed(market)
+    }
+
+    #[test]
+    fn depth_diffs_follow_on_from_snapshot_taken_at_subscription() {
+        for warm_up in [0, 3] {
+            let clock = SimulatedClock::manual(1_700_000_000_000_000);
+            let mut feed = sol_feed(7);
+            for _ in 0..warm_up {
+                clock.advance(STEP);
+                feed.tick(&clock, STEP);
+            }
+            feed.subscribe("depth.SOL_USDC".parse().unwrap());
+            let mut book = OrderBook::from_snapshot(&feed.simulator().depth_snapshot());
+            for _ in 0..20 {
+                clock.advance(STEP);
+                for (_, message) in feed.tick(&clock, STEP) {
+                    let envelope: StreamEnvelope<DepthStream> =
+                        serde_json::from_str(message.to_text().unwrap()).unwrap();
+                    assert!(book.apply(&envelope.data).unwrap());
+                }
+            }
+            assert_eq!(book.last_update_id(), Some(feed.simulator().update_id()));
+        }
+    }
+
+    #[test]
+    fn depth_diffs_chain_and_rebuild_the_simulated_book() {
+        // Every step, and batched over three steps so several book updates merge into one event.
+        for publish_millis in [None, Some(3000)] {
+            let market = MarketRegistry::with_defaults()
+                .get("SOL_USDC")
+                .cloned()
+                .unwrap();
+            let config = SimulationConfig {
+                seed: Some(11),
+                publish_interval_millis: publish_millis
+                    .map(|millis| (EventType::Depth, millis))
+                    .into_iter()
+                    .collect(),
+                ..SimulationConfig::default()
+            };
+            let mut feed = config.feed(market);
+            let clock = SimulatedClock::manual(1_700_000_000_000_000);
+            feed.subscribe("depth.SOL_USDC".parse().unwrap());
+            let snapshot = feed.simulator().depth_snapshot();
+            let mut book = OrderBook::from_snapshot(&snapshot);
+            let mut last = snapshot.last_update_id;
+            let mut events = 0;
+            for _ in 0..30 {
+                clock.advance(STEP);
+                for (_, message) in feed.tick(&clock, STEP) {
+                    let envelope: StreamEnvelope<DepthStream> =
+                        serde_json::from_str(message.to_text().unwrap()).unwrap();
+                    let depth = envelope.data;
+                    assert_eq!(depth.first_update_id(), last + 1);
+                    assert!(depth.final_update_id() >= depth.first_update_id());
+                    last = depth.final_update_id();
+                    book.apply(&depth).unwrap();
+                    events += 1;
+                    // A published event covers every update so far, so the books agree.
+                    let simulator = feed.simulator();
+                    assert_eq!(last, simulator.update_id());
+                    assert_eq!(
+                        book.bids().collect::<Vec<_>>(),
+                        simulator.bids().collect::<Vec<_>>()
+                    );
+                    assert_eq!(
+                        book.asks().collect::<Vec<_>>(),
+                        simulator.asks().collect::<Vec<_>>()
+                    );
+                }
+            }
+            assert!(events >= 10, "{} events", events);
+        }
+    }
+
+    fn subscribe_all(feed: &mut MarketFeed) {
+        for name in [
+            "depth.SOL_USDC",
+            "trade.SOL_USDC",
+            "bookTicker.SOL_USDC",
+            "ticker.SOL_USDC",
+            "kline.1m.SOL_USDC",
+        ] {
+            feed.subscribe(name.parse().unwrap());
+        }
+    }
+
+    #[test]
+    fn misspelled_market_settings_are_rejected() {
+        let valid = r#"{"SOL_USDC": {"initialPrice": 165.0, "priceModel": {"model": "gbm", "drift": 0.0, "volatility": 3.0}}}"#;
+        assert!(serde_json::from_str::<SimulationConfig>(valid).is_ok());
+        for invalid in [
+            r#"{"SOL_USDC": {"initialPrice": 165.0, "priceModle": {"model": "constant", "price": 1.0}}}"#,
+            r#"{"SOL_USDC": {"initialPrice": 165.0, "priceModel": {"model": "gbm", "drift": 0.0, "volatility": 3.0, "mean": 1.0}}}"#,
+        ] {
+            assert!(
+                serde_json::from_str::<SimulationConfig>(invalid).is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn seeded_feeds_emit_identical_messages() {
+        let run = || {
+            let clock = SimulatedClock::manual(1_700_000_000_000_000);
+            let mut feed = sol_feed(42);
+            subscribe_all(&mut feed);
+            (0..100)
+                .flat_map(|_| {
+                    clock.advance(STEP);
+                    feed.tick(&clock, STEP)
+                })
+                .collect::<Vec<_>>()
+        };
+        let first = run();
+        assert!(!first.is_empty());
+        assert_eq!(first, run());
+    }
+
+    #[test]
+    fn seeded_market_ignores_jitter_between_steps() {
+        let run = |jitter: u64| {
+            let clock = SimulatedClock::manual(1_700_000_000_000_000);
+            let mut feed = sol_feed(42);
+            subscribe_all(&mut feed);
+            for step in 0..100 {
+                clock.advance(STEP + Duration::from_micros(step % 7 * jitter));
+                feed.tick(&clock, STEP);
+            }
+            let simulator = feed.simulator();
+            (simulator.mid_price(), simulator.depth_snapshot().bids)
+        };
+        assert_eq!(run(0), run(1_000));
+    }
+}
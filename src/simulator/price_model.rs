@@ -0,0 +1,413 @@
+use rand::RngCore;
+use rand_distr::{Distribution, Poisson, StandardNormal};
+use serde::{Deserialize, Serialize};
+
+// Rates and volatilities are annualised, `dt` is in seconds.
+const SECONDS_PER_YEAR: f64 = 365.0 * 24.0 * 60.0 * 60.0;
+
+pub trait PriceModel: Send {
+    // The mid price `dt` seconds after `price`.
+    fn next_price(&mut self, price: f64, dt: f64, rng: &mut dyn RngCore) -> f64;
+}
+
+fn normal(rng: &mut dyn RngCore) -> f64 {
+    StandardNormal.sample(rng)
+}
+
+pub struct Constant {
+    pub price: f64,
+}
+
+impl PriceModel for Constant {
+    fn next_price(&mut self, _price: f64, _dt: f64, _rng: &mut dyn RngCore) -> f64 {
+        self.price
+    }
+}
+
+pub struct GeometricBrownianMotion {
+    pub drift: f64,
+    pub volatility: f64,
+}
+
+impl PriceModel for GeometricBrownianMotion {
+    fn next_price(&mut self, price: f64, dt: f64, rng: &mut dyn RngCore) -> f64 {
+        let dt = dt / SECONDS_PER_YEAR;
+        let exponent = (self.drift - self.volatility.powi(2) / 2.0) * dt
+            + self.volatility * dt.sqrt() * normal(rng);
+        price * exponent.exp()
+    }
+}
+
+// Mean reversion of the log price towards `mean`, `reversion` being the speed per year.
+pub struct OrnsteinUhlenbeck {
+    pub mean: f64,
+    pub reversion: f64,
+    pub volatility: f64,
+}
+
+impl PriceModel for OrnsteinUhlenbeck {
+    fn next_price(&mut self, price: f64, dt: f64, rng: &mut dyn RngCore) -> f64 {
+        let dt = dt / SECONDS_PER_YEAR;
+        let decay = (-self.reversion * dt).exp();
+        let variance = match self.reversion > 0.0 {
+            true => (1.0 - decay.powi(2)) / (2.0 * self.reversion),
+            false => dt,
+        };
+        let deviation = self.volatility * variance.sqrt();
+        let log_mean = self.mean.ln();
+        (log_mean + (price.ln() - log_mean) * decay + deviation * normal(rng)).exp()
+    }
+}
+
+// Merton jump diffusion: GBM plus `jump_intensity` jumps per year with normally
+// distributed log sizes. A large negative `jump_mean` gives flash crashes.
+pub struct JumpDiffusion {
+    pub diffusion: GeometricBrownianMotion,
+    pub jump_intensity: f64,
+    pub jump_mean: f64,
+    pub jump_volatility: f64,
+}
+
+impl PriceModel for JumpDiffusion {
+    fn next_price(&mut self, price: f64, dt: f64, rng: &mut dyn RngCore) -> f64 {
+        let price = self.diffusion.next_price(price, dt, rng);
+        let rate = self.jump_intensity * dt / SECONDS_PER_YEAR;
+        let jumps = match Poisson::new(rate) {
+            Ok(poisson) => poisson.sample(rng) as u64,
+            Err(_) => 0,
+        };
+        (0..jumps).fold(price, |price, _| {
+            price * (self.jump_mean + self.jump_volatility * normal(rng)).exp()
+        })
+    }
+}
+
+// Replays a fixed list of prices, one per step, then holds the last one or starts over.
+pub struct ScriptedPath {
+    pub prices: Vec<f64>,
+    pub repeat: bool,
+    position: usize,
+}
+
+impl ScriptedPath {
+    pub fn new(prices: Vec<f64>, repeat: bool) -> Self {
+        Self {
+            prices,
+            repeat,
+            position: 0,
+        }
+    }
+}
+
+impl PriceModel for ScriptedPath {
+    fn next_price(&mut self, price: f64, _dt: f64, _rng: &mut dyn RngCore) -> f64 {
+        if self.position == self.prices.len() && self.repeat {
+            self.position = 0;
+        }
+        match self.prices.get(self.position) {
+            Some(&next) => {
+                self.position += 1;
+                next
+            }
+            None => price,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "model", rename_all = "snake_case", deny_unknown_fields)]
+pub enum PriceModelConfig {
+    Constant {
+        price: f64,
+    },
+    Gbm {
+        drift: f64,
+        volatility: f64,
+    },
+    OrnsteinUhlenbeck {
+        mean: f64,
+        reversion: f64,
+        volatility: f64,
+    },
+    JumpDiffusion {
+        drift: f64,
+        volatility: f64,
+        jump_intensity: f64,
+        jump_mean: f64,
+        jump_volatility: f64,
+    },
+    Scripted {
+        prices: Vec<f64>,
+        #[serde(default)]
+        repeat: bool,
+    },
+}
+
+impl Default for PriceModelConfig {
+    fn default() -> Self {
+        PriceModelConfig::Gbm {
+            drift: 0.0,
+            volatility: 3.0,
+        }
+    }
+}
+
+impl PriceModelConfig {
+    // Catches parameters the models cannot work with, such as a negative volatility or a
+    // price path through zero.
+    pub fn validate(&self) -> Result<(), &'static str> {
+        let positive = |value: f64| value.is_finite() && value > 0.0;
+        let non_negative = |value: f64| value.is_finite() && value >= 0.0;
+        match self {
+            PriceModelConfig::Constant { price } if !positive(*price) => {
+                Err("price must be positive")
+            }
+            PriceModelConfig::Gbm { drift, .. } | PriceModelConfig::JumpDiffusion { drift, .. }
+                if !drift.is_finite() =>
+            {
+                Err("drift must be finite")
+            }
+            PriceModelConfig::Gbm { volatility, .. }
+            | PriceModelConfig::OrnsteinUhlenbeck { volatility, .. }
+            | PriceModelConfig::JumpDiffusion { volatility, .. }
+                if !non_negative(*volatility) =>
+            {
+                Err("volatility must not be negative")
+            }
+            PriceModelConfig::OrnsteinUhlenbeck { mean, .. } if !positive(*mean) => {
+                Err("mean must be positive")
+            }
+            PriceModelConfig::OrnsteinUhlenbeck { reversion, .. } if !non_negative(*reversion) => {
+                Err("reversion must not be negative")
+            }
+            PriceModelConfig::JumpDiffusion {
+                jump_intensity,
+                jump_mean,
+                jump_volatility,
+                ..
+            } if !non_negative(*jump_intensity)
+                || !jump_mean.is_finite()
+                || !non_negative(*jump_volatility) =>
+            {
+                Err("jump intensity and volatility must not be negative")
+            }
+            PriceModelConfig::Scripted { prices, .. } if prices.is_empty() => {
+                Err("scripted prices must not be empty")
+            }
+            PriceModelConfig::Scripted { prices, .. } if !prices.iter().all(|&p| positive(p)) => {
+                Err("scripted prices must be positive")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn build(&self) -> Box<dyn PriceModel> {
+        match self.clone() {
+            PriceModelConfig::Constant { price } => Box::new(Constant { price }),
+            PriceModelConfig::Gbm { drift, volatility } => {
+                Box::new(GeometricBrownianMotion { drift, volatility })
+            }
+            PriceModelConfig::OrnsteinUhlenbeck {
+                mean,
+                reversion,
+                volatility,
+            } => Box::new(OrnsteinUhlenbeck {
+                mean,
+                reversion,
+                volatility,
+            }),
+            PriceModelConfig::JumpDiffusion {
+                drift,
+                volatility,
+                jump_intensity,
+                jump_mean,
+                jump_volatility,
+            } => Box::new(JumpDiffusion {
+                diffusion: GeometricBrownianMotion { drift, volatility },
+                jump_intensity,
+                jump_mean,
+                jump_volatility,
+            }),
+            PriceModelConfig::Scripted { prices, repeat } => {
+                Box::new(ScriptedPath::new(prices, repeat))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    // Seconds in a day, a step long enough for annualised rates to show.
+    const DAY: f64 = 24.0 * 60.0 * 60.0;
+
+    fn model(json: &str) -> PriceModelConfig {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn path(model: &mut dyn PriceModel, start: f64, steps: usize, seed: u64) -> Vec<f64> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut price = start;
+        (0..steps)
+            .map(|_| {
+                price = model.next_price(price, DAY, &mut rng);
+                price
+            })
+            .collect()
+    }
+
+    #[test]
+    fn constant_stays_fixed() {
+        let mut constant = Constant { price: 42.5 };
+        assert!(path(&mut constant, 100.0, 10, 1)
+            .iter()
+            .all(|&price| price == 42.5));
+    }
+
+    #[test]
+    fn scripted_path_plays_in_order_then_holds() {
+        let mut held = ScriptedPath::new(vec![1.0, 2.0, 3.0], false);
+        assert_eq!(path(&mut held, 100.0, 5, 1), [1.0, 2.0, 3.0, 3.0, 3.0]);
+        let mut repeated = ScriptedPath::new(vec![1.0, 2.0, 3.0], true);
+        assert_eq!(path(&mut repeated, 100.0, 5, 1), [1.0, 2.0, 3.0, 1.0, 2.0]);
+    }
+
+    #[test]
+    fn ornstein_uhlenbeck_reverts_towards_the_mean() {
+        let mut calm = OrnsteinUhlenbeck {
+            mean: 100.0,
+            reversion: 200.0,
+            volatility: 0.0,
+        };
+        let prices = path(&mut calm, 200.0, 20, 1);
+        assert!(prices
+            .windows(2)
+            .all(|pair| pair[1] < pair[0] && pair[1] > 100.0));
+        assert!(prices[19] < 101.0);
+
+        let mut noisy = OrnsteinUhlenbeck {
+            volatility: 0.5,
+            ..calm
+        };
+        let prices = path(&mut noisy, 200.0, 1000, 7);
+        let average = prices[500..].iter().sum::<f64>() / 500.0;
+        assert!((average - 100.0).abs() < 5.0, "{}", average);
+    }
+
+    #[test]
+    fn gbm_without_volatility_grows_at_the_drift() {
+        let mut gbm = GeometricBrownianMotion {
+            drift: 0.5,
+            volatility: 0.0,
+        };
+        let mut rng = StdRng::seed_from_u64(1);
+        let price = gbm.next_price(100.0, DAY, &mut rng);
+        let expected = 100.0 * (0.5 * DAY / SECONDS_PER_YEAR).exp();
+        assert!((price - expected).abs() < 1e-9, "{} != {}", price, expected);
+    }
+
+    #[test]
+    fn jump_diffusion_without_jumps_is_gbm() {
+        let mut gbm = GeometricBrownianMotion {
+            drift: 0.1,
+            volatility: 0.8,
+        };
+        let mut jump_diffusion = JumpDiffusion {
+            diffusion: GeometricBrownianMotion {
+                drift: 0.1,
+                volatility: 0.8,
+            },
+            jump_intensity: 0.0,
+            jump_mean: -0.5,
+            jump_volatility: 0.2,
+        };
+        assert_eq!(
+            path(&mut jump_diffusion, 100.0, 50, 3),
+            path(&mut gbm, 100.0, 50, 3)
+        );
+    }
+
+    #[test]
+    fn accepts_usable_models() {
+        for json in [
+            r#"{"model": "constant", "price": 10.0}"#,
+            r#"{"model": "gbm", "drift": -0.5, "volatility": 0.0}"#,
+            r#"{"model": "ornstein_uhlenbeck", "mean": 100.0, "reversion": 0.0, "volatility": 1.0}"#,
+            r#"{"model": "jump_diffusion", "drift": 0.0, "volatility": 1.0, "jump_intensity": 2.0, "jump_mean": -0.1, "jump_volatility": 0.2}"#,
+            r#"{"model": "scripted", "prices": [1.0, 2.0], "repeat": true}"#,
+        ] {
+            assert_eq!(model(json).validate(), Ok(()), "{}", json);
+        }
+        assert_eq!(PriceModelConfig::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn rejects_unusable_models() {
+        let cases = [
+            (
+                PriceModelConfig::Constant { price: 0.0 },
+                "price must be positive",
+            ),
+            (
+                PriceModelConfig::Gbm {
+                    drift: f64::NAN,
+                    volatility: 1.0,
+                },
+                "drift must be finite",
+            ),
+            (
+                PriceModelConfig::Gbm {
+                    drift: 0.0,
+                    volatility: -1.0,
+                },
+                "volatility must not be negative",
+            ),
+            (
+                PriceModelConfig::OrnsteinUhlenbeck {
+                    mean: -5.0,
+                    reversion: 1.0,
+                    volatility: 1.0,
+                },
+                "mean must be positive",
+            ),
+            (
+                PriceModelConfig::OrnsteinUhlenbeck {
+                    mean: 5.0,
+                    reversion: -1.0,
+                    volatility: 1.0,
+                },
+                "reversion must not be negative",
+            ),
+            (
+                PriceModelConfig::JumpDiffusion {
+                    drift: 0.0,
+                    volatility: 1.0,
+                    jump_intensity: -1.0,
+                    jump_mean: 0.0,
+                    jump_volatility: 0.1,
+                },
+                "jump intensity and volatility must not be negative",
+            ),
+            (
+                PriceModelConfig::Scripted {
+                    prices: vec![],
+                    repeat: false,
+                },
+                "scripted prices must not be empty",
+            ),
+            (
+                PriceModelConfig::Scripted {
+                    prices: vec![1.0, 0.0],
+                    repeat: false,
+                },
+                "scripted prices must be positive",
+            ),
+        ];
+        for (model, reason) in cases {
+            assert_eq!(model.validate(), Err(reason), "{:?}", model);
+        }
+    }
+}
@@ -0,0 +1,358 @@
+use crate::envelope::StreamEnvelope;
+use crate::error::SinkError;
+use crate::event_type::Event;
+use crate::recording::{Compression, RecordedEvent};
+use async_trait::async_trait;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+pub mod file;
+pub mod memory;
+pub mod redis;
+pub mod stdout;
+
+pub use file::{FileSink, RotatingFileSink};
+pub use memory::MemorySink;
+pub use redis::{
+    RedisPublishSink, RedisStreamReader, RedisStreamSink, StreamRecord, DEFAULT_CHANNEL,
+    DEFAULT_MAX_LEN,
+};
+pub use stdout::StdoutSink;
+
+// Somewhere recorded events go. Writes may be buffered, so `flush` or `close` has to be
+// called before the sink is dropped for everything to reach its destination.
+#[async_trait]
+pub trait Sink: Send {
+    // `received_at` is when the event came in, in Unix microseconds, stamped once for all
+    // sinks so they record the same time however long they take to write.
+    async fn write(
+        &mut self,
+        envelope: &StreamEnvelope<Event>,
+        received_at: u64,
+    ) -> Result<(), SinkError>;
+    async fn flush(&mut self) -> Result<(), SinkError>;
+
+    // Flushes and finishes whatever is still open, nothing is written afterwards.
+    async fn close(&mut self) -> Result<(), SinkError> {
+        self.flush().await
+    }
+}
+
+// Writes every event to each of its sinks. One failing sink does not keep the event from
+// the others, the first error is returned once all have been tried.
+#[derive(Default)]
+pub struct MultiSink {
+    sinks: Vec<Box<dyn Sink>>,
+}
+
+impl MultiSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_sink(mut self, sink: Box<dyn Sink>) -> Self {
+        self.sinks.push(sink);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sinks.is_empty()
+    }
+}
+
+#[async_trait]
+impl Sink for MultiSink {
+    async fn write(
+        &mut self,
+        envelope: &StreamEnvelope<Event>,
+        received_at: u64,
+    ) -> Result<(), SinkError> {
+        let mut result = Ok(());
+        for sink in &mut self.sinks {
+            let written = sink.write(envelope, received_at).await;
+            result = result.and(written);
+        }
+        result
+    }
+
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        let mut result = Ok(());
+        for sink in &mut self.sinks {
+            let flushed = sink.flush().await;
+            result = result.and(flushed);
+        }
+        result
+    }
+
+    async fn close(&mut self) -> Result<(), SinkError> {
+        let mut result = Ok(());
+        for sink in &mut self.sinks {
+            let closed = sink.close().await;
+            result = result.and(closed);
+        }
+        result
+    }
+}
+
+// A sink as given on the command line:
+// `stdout`, `file:./{stream}.ndjson`, `rotate:64:./recordings/backpack`,
+// `rotate:64,1h,zstd:./recordings/backpack`,
+// `redis:redis://127.0.0.1/`, `redis:redis://127.0.0.1/#ticks:{stream}`,
+// `redis-stream:redis://127.0.0.1/`, `redis-stream:redis://127.0.0.1/#10000` or `memory:10000`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SinkSpec {
+    Stdout,
+    // A `{stream}` in the path gives every stream its own file.
+    File(String),
+    // Options before the prefix are comma separated: a plain number is the size limit in
+    // megabytes, a number with `s`, `m` or `h` the age limit, and `gzip` or `zstd` how
+    // closed files are compressed. At least one limit is needed.
+    Rotate {
+        max_megabytes: Option<u64>,
+        max_age: Option<Duration>,
+        compression: Compression,
+        prefix: PathBuf,
+    },
+    // Publishes to the channel after the `#`, `backpack:{stream}` if there is none.
+    Redis {
+        url: String,
+        channel: String,
+    },
+    // Trims each stream to about the length after the `#`, `DEFAULT_MAX_LEN` if there is none.
+    RedisStream {
+        url: String,
+        max_len: usize,
+    },
+    Memory(usize),
+}
+
+impl SinkSpec {
+    pub fn open(&self) -> Result<Box<dyn Sink>, SinkError> {
+        Ok(match self {
+            SinkSpec::Stdout => Box::new(StdoutSink::new()),
+            SinkSpec::File(path) => Box::new(FileSink::new(path)),
+            SinkSpec::Rotate {
+                max_megabytes,
+                max_age,
+                compression,
+                prefix,
+            } => {
+                let mut sink = RotatingFileSink::new(prefix).with_compression(*compression);
+                if let Some(max_megabytes) = max_megabytes {
+                    sink = sink.with_max_bytes(max_megabytes * 1024 * 1024);
+                }
+                if let Some(max_age) = max_age {
+                    sink = sink.with_max_age(*max_age);
+                }
+                Box::new(sink)
+            }
+            SinkSpec::Redis { url, channel } => {
+                Box::new(RedisPublishSink::open(url)?.with_channel(channel))
+            }
+            SinkSpec::RedisStream { url, max_len } => {
+                Box::new(RedisStreamSink::open(url)?.with_max_len(*max_len))
+            }
+            SinkSpec::Memory(capacity) => Box::new(MemorySink::new(*capacity)),
+        })
+    }
+}
+
+impl FromStr for SinkSpec {
+    type Err = SinkError;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let invalid = || SinkError::InvalidSpec(spec.to_string());
+        let (kind, target) = spec.split_once(':').unwrap_or((spec, ""));
+        match (kind, target) {
+            ("stdout", "") => Ok(SinkSpec::Stdout),
+            (_, "") => Err(invalid()),
+            ("file", path) => Ok(SinkSpec::File(path.to_string())),
+            ("rotate", target) => {
+                let (options, prefix) = target.split_once(':').ok_or_else(invalid)?;
+                let (mut max_megabytes, mut max_age, mut compression) =
+                    (None, None, Compression::None);
+                for option in options.split(',') {
+                    if let Ok(parsed) = option.parse() {
+                        compression = parsed;
+                    } else if let Some(age) = parse_age(option) {
+                        max_age = Some(age);
+                    } else {
+                        max_megabytes = Some(option.parse().map_err(|_| invalid())?);
+                    }
+                }
+                if prefix.is_empty()
+                    || max_megabytes == Some(0)
+                    || max_age.is_some_and(|age| age.is_zero())
+                    || (max_megabytes.is_none() && max_age.is_none())
+                {
+                    return Err(invalid());
+                }
+                Ok(SinkSpec::Rotate {
+                    max_megabytes,
+                    max_age,
+                    compression,
+                    prefix: PathBuf::from(prefix),
+                })
+            }
+            ("redis", target) => {
+                let (url, channel) = target.split_once('#').unwrap_or((target, DEFAULT_CHANNEL));
+                Ok(SinkSpec::Redis {
+                    url: url.to_string(),
+                    channel: channel.to_string(),
+                })
+            }
+            ("redis-stream", target) => {
+                let (url, max_len) = match target.split_once('#') {
+                    Some((url, max_len)) => (
+                        url,
+                        max_len
+                            .parse()
+                            .ok()
+                            .filter(|&max_len| max_len > 0)
+                            .ok_or_else(invalid)?,
+                    ),
+                    None => (target, DEFAULT_MAX_LEN),
+                };
+                Ok(SinkSpec::RedisStream {
+                    url: url.to_string(),
+                    max_len,
+                })
+            }
+            ("memory", capacity) => Ok(SinkSpec::Memory(
+                capacity
+                    .parse()
+                    .ok()
+                    .filter(|&capacity| capacity > 0)
+                    .ok_or_else(invalid)?,
+            )),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+// `30s`, `15m` or `1h`.
+fn parse_age(age: &str) -> Option<Duration> {
+    let unit = match age.chars().last()? {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        _ => return None,
+    };
+    let count: u64 = age[..age.len() - 1].parse().ok()?;
+    Some(Duration::from_secs(count * unit))
+}
+
+// One `RecordedEvent` per line, the format every file and stdout sink writes.
+pub fn ndjson_line(
+    envelope: &StreamEnvelope<Event>,
+    received_at: u64,
+) -> Result<String, SinkError> {
+    let mut line = serde_json::to_string(&RecordedEvent::new(envelope, received_at))?;
+    line.push('\n');
+    Ok(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(spec: &str) -> SinkSpec {
+        spec.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_sink_specs() {
+        assert_eq!(spec("stdout"), SinkSpec::Stdout);
+        assert_eq!(
+            spec("file:./{stream}.ndjson"),
+            SinkSpec::File("./{stream}.ndjson".to_string())
+        );
+        assert_eq!(
+            spec("redis:redis://127.0.0.1/"),
+            SinkSpec::Redis {
+                url: "redis://127.0.0.1/".to_string(),
+                channel: DEFAULT_CHANNEL.to_string(),
+            }
+        );
+        assert_eq!(
+            spec("redis:redis://127.0.0.1/#ticks:{stream}"),
+            SinkSpec::Redis {
+                url: "redis://127.0.0.1/".to_string(),
+                channel: "ticks:{stream}".to_string(),
+            }
+        );
+        assert_eq!(
+            spec("redis-stream:redis://127.0.0.1/#500"),
+            SinkSpec::RedisStream {
+                url: "redis://127.0.0.1/".to_string(),
+                max_len: 500,
+            }
+        );
+        assert_eq!(
+            spec("redis-stream:redis://127.0.0.1/"),
+            SinkSpec::RedisStream {
+                url: "redis://127.0.0.1/".to_string(),
+                max_len: DEFAULT_MAX_LEN,
+            }
+        );
+        assert_eq!(spec("memory:100"), SinkSpec::Memory(100));
+    }
+
+    #[test]
+    fn parses_rotation_options() {
+        assert_eq!(
+            spec("rotate:64:./recordings/backpack"),
+            SinkSpec::Rotate {
+                max_megabytes: Some(64),
+                max_age: None,
+                compression: Compression::None,
+                prefix: PathBuf::from("./recordings/backpack"),
+            }
+        );
+        assert_eq!(
+            spec("rotate:15m,zstd:./rec"),
+            SinkSpec::Rotate {
+                max_megabytes: None,
+                max_age: Some(Duration::from_secs(15 * 60)),
+                compression: Compression::Zstd,
+                prefix: PathBuf::from("./rec"),
+            }
+        );
+        assert_eq!(
+            spec("rotate:gzip,1h,8:./rec"),
+            SinkSpec::Rotate {
+                max_megabytes: Some(8),
+                max_age: Some(Duration::from_secs(60 * 60)),
+                compression: Compression::Gzip,
+                prefix: PathBuf::from("./rec"),
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_sink_specs() {
+        for spec in [
+            "",
+            "stdout:x",
+            "file:",
+            "memory:0",
+            "rotate:64",
+            "rotate:64:",
+            "rotate:0:./rec",
+            "rotate:0s:./rec",
+            "rotate:gzip:./rec",
+            "rotate:64,brotli:./rec",
+            "rotate:1d:./rec",
+            "memory:many",
+            "redis-stream:redis://127.0.0.1/#0",
+            "kafka:localhost",
+        ] {
+            assert!(
+                matches!(spec.parse::<SinkSpec>(), Err(SinkError::InvalidSpec(_))),
+                "{}",
+                spec
+            );
+        }
+    }
+}
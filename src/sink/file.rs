@@ -0,0 +1,213 @@
+use super::{ndjson_line, Sink};
+use crate::envelope::StreamEnvelope;
+use crate::error::SinkError;
+use crate::event_type::Event;
+use crate::recording::{compress, Compression};
+use async_trait::async_trait;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::task::{spawn_blocking, JoinHandle};
+use tracing::{info, warn};
+
+async fn append(path: &Path) -> Result<BufWriter<File>, SinkError> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    Ok(BufWriter::new(file))
+}
+
+// Appends NDJSON to a file opened once and kept open. A `{stream}` in the path is replaced
+// by the stream name, giving every stream its own file.
+pub struct FileSink {
+    template: String,
+    files: BTreeMap<String, BufWriter<File>>,
+}
+
+impl FileSink {
+    pub fn new(template: impl Into<String>) -> Self {
+        Self {
+            template: template.into(),
+            files: BTreeMap::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl Sink for FileSink {
+    async fn write(
+        &mut self,
+        envelope: &StreamEnvelope<Event>,
+        received_at: u64,
+    ) -> Result<(), SinkError> {
+        let path = self.template.replace("{stream}", &envelope.stream);
+        if !self.files.contains_key(&path) {
+            let file = append(Path::new(&path)).await?;
+            self.files.insert(path.clone(), file);
+        }
+        let file = self.files.get_mut(&path).unwrap();
+        file.write_all(ndjson_line(envelope, received_at)?.as_bytes())
+            .await?;
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        for file in self.files.values_mut() {
+            file.flush().await?;
+        }
+        Ok(())
+    }
+}
+
+// Writes NDJSON to `<prefix>-<start>-<n>.ndjson`, moving on to the next file once the
+// current one has reached `max_bytes` or has been open for `max_age`. `start` is when the
+// sink was created, in Unix milliseconds, so files from separate runs never collide.
+// Finished files are compressed in the background, the last one when the sink is closed.
+pub struct RotatingFileSink {
+    prefix: PathBuf,
+    max_bytes: Option<u64>,
+    max_age: Option<Duration>,
+    compression: Compression,
+    start: u128,
+    sequence: u32,
+    current: Option<Segment>,
+    compressing: Vec<JoinHandle<io::Result<PathBuf>>>,
+}
+
+struct Segment {
+    file: BufWriter<File>,
+    path: PathBuf,
+    written: u64,
+    opened: Instant,
+}
+
+impl RotatingFileSink {
+    pub fn new(prefix: impl Into<PathBuf>) -> Self {
+        Self {
+            prefix: prefix.into(),
+            max_bytes: None,
+            max_age: None,
+            compression: Compression::None,
+            start: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_millis()),
+            sequence: 0,
+            current: None,
+            compressing: Vec::new(),
+        }
+    }
+
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    fn path(&self, sequence: u32) -> PathBuf {
+        let mut path = self.prefix.clone().into_os_string();
+        path.push(format!("-{}-{:04}.ndjson", self.start, sequence));
+        path.into()
+    }
+
+    fn is_full(&self, segment: &Segment) -> bool {
+        self.max_bytes.is_some_and(|max| segment.written >= max)
+            || self
+                .max_age
+                .is_some_and(|max| segment.opened.elapsed() >= max)
+    }
+
+    async fn finish_segment(&mut self) -> Result<(), SinkError> {
+        let Some(mut segment) = self.current.take() else {
+            return Ok(());
+        };
+        segment.file.flush().await?;
+        drop(segment.file);
+        if self.compression != Compression::None {
+            let compression = self.compression;
+            self.compressing.retain(|task| !task.is_finished());
+            self.compressing.push(spawn_blocking(move || {
+                let compressed = compress(&segment.path, compression);
+                match &compressed {
+                    Ok(path) => info!("Compressed recording to: {}", path.display()),
+                    Err(e) => warn!("Failed to compress {}: {}", segment.path.display(), e),
+                }
+                compressed
+            }));
+        }
+        Ok(())
+    }
+
+    async fn rotate(&mut self) -> Result<(), SinkError> {
+        self.finish_segment().await?;
+        let path = self.path(self.sequence + 1);
+        let file = append(&path).await?;
+        info!("Recording to: {}", path.display());
+        self.sequence += 1;
+        self.current = Some(Segment {
+            file,
+            path,
+            written: 0,
+            opened: Instant::now(),
+        });
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Sink for RotatingFileSink {
+    async fn write(
+        &mut self,
+        envelope: &StreamEnvelope<Event>,
+        received_at: u64,
+    ) -> Result<(), SinkError> {
+        let line = ndjson_line(envelope, received_at)?;
+        if self
+            .current
+            .as_ref()
+            .is_none_or(|segment| self.is_full(segment))
+        {
+            self.rotate().await?;
+        }
+        let segment = self.current.as_mut().unwrap();
+        segment.file.write_all(line.as_bytes()).await?;
+        segment.written += line.len() as u64;
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        if let Some(segment) = &mut self.current {
+            segment.file.flush().await?;
+        }
+        Ok(())
+    }
+
+    async fn close(&mut self) -> Result<(), SinkError> {
+        self.finish_segment().await?;
+        let mut result = Ok(());
+        for task in self.compressing.drain(..) {
+            let compressed = match task.await {
+                Ok(compressed) => compressed.map(drop).map_err(SinkError::from),
+                Err(e) => Err(SinkError::Io(io::Error::other(e))),
+            };
+            result = result.and(compressed);
+        }
+        result
+    }
+}
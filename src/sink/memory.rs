@@ -0,0 +1,107 @@
+use super::Sink;
+use crate::envelope::StreamEnvelope;
+use crate::error::SinkError;
+use crate::event_type::Event;
+use async_trait::async_trait;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+// Keeps the last `capacity` events, none at all with a capacity of 0. Clones share the
+// buffer, so one clone can be handed to a recorder while another reads what it recorded.
+#[derive(Clone)]
+pub struct MemorySink {
+    capacity: usize,
+    events: Arc<Mutex<VecDeque<StreamEnvelope<Event>>>>,
+}
+
+impl MemorySink {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            events: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Oldest first.
+    pub fn events(&self) -> Vec<StreamEnvelope<Event>> {
+        self.events.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn clear(&self) {
+        self.events.lock().unwrap().clear();
+    }
+}
+
+#[async_trait]
+impl Sink for MemorySink {
+    async fn write(
+        &mut self,
+        envelope: &StreamEnvelope<Event>,
+        _received_at: u64,
+    ) -> Result<(), SinkError> {
+        if self.capacity == 0 {
+            return Ok(());
+        }
+        let mut events = self.events.lock().unwrap();
+        if events.len() == self.capacity {
+            events.pop_front();
+        }
+        events.push_back(envelope.clone());
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn trade(id: u64) -> StreamEnvelope<Event> {
+        let data = json!({
+            "e": "trade", "E": 1, "s": "SOL_USDC", "p": "18.68", "q": "0.122",
+            "b": "1", "a": "2", "t": id, "T": 1, "m": true,
+        });
+        StreamEnvelope::new("trade.SOL_USDC", serde_json::from_value(data).unwrap())
+    }
+
+    #[tokio::test]
+    async fn keeps_only_the_latest_events() {
+        let mut sink = MemorySink::new(2);
+        for id in 1..=3 {
+            sink.write(&trade(id), 0).await.unwrap();
+        }
+        let ids: Vec<_> = sink
+            .events()
+            .iter()
+            .map(|envelope| serde_json::to_value(&envelope.data).unwrap()["t"].clone())
+            .collect();
+        assert_eq!(ids, [2, 3]);
+    }
+
+    #[test]
+    fn buffer_grows_with_the_events_rather_than_the_capacity() {
+        let sink = MemorySink::new(100_000_000);
+        assert!(sink.events.lock().unwrap().capacity() < 1000);
+    }
+
+    #[tokio::test]
+    async fn zero_capacity_stores_nothing() {
+        let mut sink = MemorySink::new(0);
+        for id in 1..=3 {
+            sink.write(&trade(id), 0).await.unwrap();
+        }
+        assert!(sink.is_empty());
+    }
+}
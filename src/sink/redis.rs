@@ -0,0 +1,601 @@
+use super::Sink;
+use crate::client::ReconnectPolicy;
+use crate::envelope::StreamEnvelope;
+use crate::error::SinkError;
+use crate::event_type::Event;
+use async_trait::async_trait;
+use redis::aio::{ConnectionLike, MultiplexedConnection};
+use redis::streams::{StreamId, StreamReadOptions, StreamReadReply};
+use redis::{AsyncCommands, Client, Cmd, RedisResult};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::sleep;
+use tracing::{info, warn};
+
+pub const DEFAULT_CHANNEL: &str = "backpack:{stream}";
+
+pub const DEFAULT_STREAM_KEY: &str = "backpack:{symbol}";
+
+pub const DEFAULT_MAX_LEN: usize = 100_000;
+
+// Commands waiting for Redis. Past this, new ones are dropped rather than holding up the
+// websocket the events come from.
+const QUEUE_LEN: usize = 10_000;
+
+// Commands sent to Redis in one round trip at most.
+const MAX_PIPELINE: usize = 500;
+
+enum Request {
+    Command(Cmd),
+    Flush(oneshot::Sender<()>),
+}
+
+// Sends commands from a background task, so a slow or unreachable Redis never blocks the
+// caller. Whatever has queued up while a round trip was in flight goes out as one
+// pipeline, and a failed connection is re-established with backoff and the pipeline retried.
+// Delivery is at least once: a retried pipeline may repeat commands Redis already ran. A
+// pipeline Redis answers with an error, say WRONGTYPE, is not retried, since every retry
+// would fail the same way; the commands in it that succeeded stay applied.
+struct RedisWriter {
+    requests: mpsc::Sender<Request>,
+    connected: Arc<AtomicBool>,
+    dropped: u64,
+}
+
+// Where the writer gets its connections from, a Redis server outside of tests.
+#[async_trait]
+trait Connect: Send + Sync + 'static {
+    type Connection: ConnectionLike + Send;
+
+    async fn connect(&self) -> RedisResult<Self::Connection>;
+}
+
+#[async_trait]
+impl Connect for Client {
+    type Connection = MultiplexedConnection;
+
+    async fn connect(&self) -> RedisResult<MultiplexedConnection> {
+        self.get_multiplexed_async_connection().await
+    }
+}
+
+impl RedisWriter {
+    fn open(url: &str) -> Result<Self, SinkError> {
+        Ok(Self::with_connector(Client::open(url)?))
+    }
+
+    fn with_connector(connector: impl Connect) -> Self {
+        let (requests, rx) = mpsc::channel(QUEUE_LEN);
+        let connected = Arc::new(AtomicBool::new(false));
+        tokio::spawn(run(connector, rx, connected.clone()));
+        Self {
+            requests,
+            connected,
+            dropped: 0,
+        }
+    }
+
+    fn send(&mut self, cmd: Cmd) -> Result<(), SinkError> {
+        match self.requests.try_send(Request::Command(cmd)) {
+            Ok(()) => {
+                if self.dropped > 0 {
+                    info!("Redis caught up, {} messages were dropped", self.dropped);
+                    self.dropped = 0;
+                }
+                Ok(())
+            }
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                if self.dropped == 0 {
+                    warn!("Redis is falling behind, dropping messages");
+                }
+                self.dropped += 1;
+                Ok(())
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => Err(SinkError::Closed),
+        }
+    }
+
+    // Waits for everything sent so far, unless Redis is unreachable, in which case the
+    // commands stay queued and waiting would only stall the caller.
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        if !self.connected.load(Ordering::Relaxed) {
+            return Err(SinkError::Unavailable);
+        }
+        let (done, flushed) = oneshot::channel();
+        self.requests
+            .send(Request::Flush(done))
+            .await
+            .map_err(|_| SinkError::Closed)?;
+        flushed.await.map_err(|_| SinkError::Closed)
+    }
+}
+
+async fn run<C: Connect>(
+    connector: C,
+    mut rx: mpsc::Receiver<Request>,
+    connected: Arc<AtomicBool>,
+) {
+    let mut connection = connect(&connector, &connected).await;
+    let mut batch = Vec::with_capacity(MAX_PIPELINE);
+    while rx.recv_many(&mut batch, MAX_PIPELINE).await > 0 {
+        let mut pipe = redis::pipe();
+        let mut waiting = Vec::new();
+        for request in batch.drain(..) {
+            match request {
+                Request::Command(cmd) => {
+                    pipe.add_command(cmd).ignore();
+                }
+                Request::Flush(done) => waiting.push(done),
+            }
+        }
+        loop {
+            match pipe.query_async::<_, ()>(&mut connection).await {
+                Ok(()) => break,
+                // Dropped and refused connections are IO errors too.
+                Err(e) if e.is_io_error() => {
+                    warn!("Redis write failed, reconnecting: {}", e);
+                    connected.store(false, Ordering::Relaxed);
+                    connection = connect(&connector, &connected).await;
+                }
+                Err(e) => {
+                    warn!("Redis rejected a write, discarding its batch: {}", e);
+                    break;
+                }
+            }
+        }
+        for done in waiting {
+            let _ = done.send(());
+        }
+    }
+}
+
+async fn connect<C: Connect>(connector: &C, connected: &AtomicBool) -> C::Connection {
+    let policy = ReconnectPolicy::default();
+    let mut attempt = 0;
+    loop {
+        match connector.connect().await {
+            Ok(connection) => {
+                info!("Connected to Redis");
+                connected.store(true, Ordering::Relaxed);
+                return connection;
+            }
+            Err(e) => {
+                attempt += 1;
+                warn!("Failed to connect to Redis (attempt {}): {}", attempt, e);
+                sleep(policy.delay(attempt)).await;
+            }
+        }
+    }
+}
+
+// PUBLISHes every event, as the JSON of its envelope, to a channel named after its stream.
+// A `{stream}` in the channel is replaced by the stream name, e.g. `backpack:depth.SOL_USDC`.
+pub struct RedisPublishSink {
+    writer: RedisWriter,
+    channel: String,
+}
+
+impl RedisPublishSink {
+    pub fn open(url: &str) -> Result<Self, SinkError> {
+        Ok(Self {
+            writer: RedisWriter::open(url)?,
+            channel: DEFAULT_CHANNEL.to_string(),
+        })
+    }
+
+    pub fn with_channel(mut self, channel: impl Into<String>) -> Self {
+        self.channel = channel.into();
+        self
+    }
+}
+
+#[async_trait]
+impl Sink for RedisPublishSink {
+    async fn write(
+        &mut self,
+        envelope: &StreamEnvelope<Event>,
+        _received_at: u64,
+    ) -> Result<(), SinkError> {
+        let mut cmd = redis::cmd("PUBLISH");
+        cmd.arg(self.channel.replace("{stream}", &envelope.stream))
+            .arg(serde_json::to_string(envelope)?);
+        self.writer.send(cmd)
+    }
+
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        self.writer.flush().await
+    }
+}
+
+// XADDs every event to a Redis stream per market, `backpack:SOL_USDC` by default, trimmed to
+// about `max_len` entries. Besides the event JSON under `data`, each entry carries the
+// stream name, the event time `E` and whatever update IDs the event has (`U` and `u` for
+// depth, `u` for book tickers, `t` for trades), so consumers can filter without decoding.
+pub struct RedisStreamSink {
+    writer: RedisWriter,
+    key: String,
+    max_len: usize,
+}
+
+impl RedisStreamSink {
+    pub fn open(url: &str) -> Result<Self, SinkError> {
+        Ok(Self {
+            writer: RedisWriter::open(url)?,
+            key: DEFAULT_STREAM_KEY.to_string(),
+            max_len: DEFAULT_MAX_LEN,
+        })
+    }
+
+    // `{symbol}` and `{stream}` in the key are replaced by the event's symbol and stream.
+    pub fn with_key(mut self, key: impl Into<String>) -> Self {
+        self.key = key.into();
+        self
+    }
+
+    pub fn with_max_len(mut self, max_len: usize) -> Self {
+        self.max_len = max_len;
+        self
+    }
+}
+
+#[async_trait]
+impl Sink for RedisStreamSink {
+    async fn write(
+        &mut self,
+        envelope: &StreamEnvelope<Event>,
+        _received_at: u64,
+    ) -> Result<(), SinkError> {
+        let event = &envelope.data;
+        let key = self
+            .key
+            .replace("{symbol}", event.symbol().as_str())
+            .replace("{stream}", &envelope.stream);
+        let mut cmd = redis::cmd("XADD");
+        cmd.arg(key)
+            .arg("MAXLEN")
+            .arg("~")
+            .arg(self.max_len)
+            .arg("*")
+            .arg("stream")
+            .arg(&envelope.stream)
+            .arg("E")
+            .arg(event.event_time());
+        match event {
+            Event::Depth(depth) => {
+                cmd.arg("U")
+                    .arg(depth.first_update_id())
+                    .arg("u")
+                    .arg(depth.final_update_id());
+            }
+            Event::BookTicker(book_ticker) => {
+                cmd.arg("u").arg(book_ticker.update_id());
+            }
+            Event::Trade(trade) => {
+                cmd.arg("t").arg(trade.trade_id());
+            }
+            Event::Kline(_) | Event::Ticker(_) => {}
+        }
+        cmd.arg("data").arg(serde_json::to_string(event)?);
+        self.writer.send(cmd)
+    }
+
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        self.writer.flush().await
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StreamRecord {
+    pub key: String,
+    pub id: String,
+    pub envelope: StreamEnvelope<Event>,
+}
+
+// Reads what a `RedisStreamSink` wrote as one consumer of a consumer group. A group that
+// does not exist yet is created at the start of the streams, so a new consumer sees their
+// whole retained history. On open the consumer first gets back everything it was handed
+// before but never acknowledged, then new entries as they arrive.
+pub struct RedisStreamReader {
+    connection: MultiplexedConnection,
+    keys: Vec<String>,
+    group: String,
+    consumer: String,
+    count: usize,
+    block: Duration,
+    // Where each key's unacknowledged backlog continues, until it runs dry.
+    pending: Option<Vec<String>>,
+}
+
+impl RedisStreamReader {
+    pub async fn open(
+        url: &str,
+        keys: Vec<String>,
+        group: impl Into<String>,
+        consumer: impl Into<String>,
+    ) -> Result<Self, SinkError> {
+        let group = group.into();
+        let mut connection = Client::open(url)?
+            .get_multiplexed_async_connection()
+            .await?;
+        for key in &keys {
+            let created: redis::RedisResult<()> =
+                connection.xgroup_create_mkstream(key, &group, "0").await;
+            match created {
+                Err(e) if e.code() == Some("BUSYGROUP") => {}
+                created => created?,
+            }
+        }
+        let pending = Some(vec!["0".to_string(); keys.len()]);
+        Ok(Self {
+            connection,
+            keys,
+            group,
+            consumer: consumer.into(),
+            count: 100,
+            block: Duration::from_secs(5),
+            pending,
+        })
+    }
+
+    pub fn with_count(mut self, count: usize) -> Self {
+        self.count = count;
+        self
+    }
+
+    // How long `next_batch` waits for new entries before returning an empty batch.
+    pub fn with_block(mut self, block: Duration) -> Self {
+        self.block = block;
+        self
+    }
+
+    pub async fn next_batch(&mut self) -> Result<Vec<StreamRecord>, SinkError> {
+        loop {
+            // An empty backlog only means it is time to wait for new entries.
+            let replaying = self.pending.is_some();
+            let records = self.read().await?;
+            if !(records.is_empty() && replaying) {
+                return Ok(records);
+            }
+        }
+    }
+
+    async fn read(&mut self) -> Result<Vec<StreamRecord>, SinkError> {
+        let options = StreamReadOptions::default()
+            .group(&self.group, &self.consumer)
+            .count(self.count);
+        let (ids, options) = match &self.pending {
+            Some(pending) => (pending.clone(), options),
+            None => (
+                vec![">".to_string(); self.keys.len()],
+                options.block(self.block.as_millis() as usize),
+            ),
+        };
+        let reply: Option<StreamReadReply> = self
+            .connection
+            .xread_options(&self.keys, &ids, &options)
+            .await?;
+        let reply = reply.unwrap_or_default();
+        if let Some(pending) = &mut self.pending {
+            let mut drained = true;
+            for stream in &reply.keys {
+                let (Some(last), Some(index)) = (
+                    stream.ids.last(),
+                    self.keys.iter().position(|key| *key == stream.key),
+                ) else {
+                    continue;
+                };
+                pending[index] = last.id.clone();
+                drained = false;
+            }
+            if drained {
+                self.pending = None;
+            }
+        }
+        let mut records = Vec::new();
+        for stream in reply.keys {
+            for entry in stream.ids {
+                match decode(&entry) {
+                    Some(envelope) => records.push(StreamRecord {
+                        key: stream.key.clone(),
+                        id: entry.id,
+                        envelope,
+                    }),
+                    // Trimmed away before it was acknowledged, or not written by us.
+                    None => {
+                        warn!("Skipping unreadable entry {} in {}", entry.id, stream.key);
+                        self.connection
+                            .xack::<_, _, _, ()>(&stream.key, &self.group, &[&entry.id])
+                            .await?;
+                    }
+                }
+            }
+        }
+        Ok(records)
+    }
+
+    pub async fn ack(&mut self, records: &[StreamRecord]) -> Result<(), SinkError> {
+        for key in &self.keys {
+            let ids: Vec<&str> = records
+                .iter()
+                .filter(|record| record.key == *key)
+                .map(|record| record.id.as_str())
+                .collect();
+            if !ids.is_empty() {
+                self.connection
+                    .xack::<_, _, _, ()>(key, &self.group, &ids)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn decode(entry: &StreamId) -> Option<StreamEnvelope<Event>> {
+    let stream: String = entry.get("stream")?;
+    let data: String = entry.get("data")?;
+    let data = serde_json::from_str(&data).ok()?;
+    Some(StreamEnvelope::new(stream, data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use redis::{Pipeline, RedisError, RedisFuture, Value};
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Mutex;
+    use tokio::time::timeout;
+
+    fn trade(symbol: &str, trade_id: u64) -> StreamEnvelope<Event> {
+        let data = serde_json::json!({
+            "e": "trade", "E": 1694688638091000u64, "s": symbol, "p": "18.68", "q": "0.122",
+            "b": "111063114377265150", "a": "111063114585735170", "t": trade_id,
+            "T": 1694688638089000u64, "m": true
+        });
+        StreamEnvelope::new(
+            format!("trade.{}", symbol),
+            serde_json::from_value(data).unwrap(),
+        )
+    }
+
+    // Stands in for Redis, failing the next pipelines with the queued errors.
+    #[derive(Clone, Default)]
+    struct FakeRedis {
+        // Commands in every pipeline that reached it, failed ones included.
+        pipelines: Arc<Mutex<Vec<usize>>>,
+        failures: Arc<Mutex<Vec<RedisError>>>,
+        connects: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl Connect for FakeRedis {
+        type Connection = FakeRedis;
+
+        async fn connect(&self) -> RedisResult<FakeRedis> {
+            self.connects.fetch_add(1, Ordering::SeqCst);
+            Ok(self.clone())
+        }
+    }
+
+    impl ConnectionLike for FakeRedis {
+        fn req_packed_command<'a>(&'a mut self, _cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+            Box::pin(async { Ok(Value::Okay) })
+        }
+
+        fn req_packed_commands<'a>(
+            &'a mut self,
+            pipeline: &'a Pipeline,
+            _offset: usize,
+            count: usize,
+        ) -> RedisFuture<'a, Vec<Value>> {
+            self.pipelines
+                .lock()
+                .unwrap()
+                .push(pipeline.cmd_iter().count());
+            let failure = self.failures.lock().unwrap().pop();
+            Box::pin(async move {
+                match failure {
+                    Some(e) => Err(e),
+                    None => Ok(vec![Value::Okay; count]),
+                }
+            })
+        }
+
+        fn get_db(&self) -> i64 {
+            0
+        }
+    }
+
+    async fn connected_writer(redis: &FakeRedis) -> RedisWriter {
+        let mut writer = RedisWriter::with_connector(redis.clone());
+        while writer.flush().await.is_err() {
+            tokio::task::yield_now().await;
+        }
+        writer
+    }
+
+    fn publish(writer: &mut RedisWriter, count: usize) {
+        for i in 0..count {
+            let mut cmd = redis::cmd("PUBLISH");
+            cmd.arg("backpack:trade.SOL_USDC").arg(i);
+            writer.send(cmd).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn queued_commands_go_out_in_pipelines() {
+        let redis = FakeRedis::default();
+        let mut writer = connected_writer(&redis).await;
+        publish(&mut writer, 3);
+        writer.flush().await.unwrap();
+        publish(&mut writer, MAX_PIPELINE + 100);
+        writer.flush().await.unwrap();
+        assert_eq!(*redis.pipelines.lock().unwrap(), [3, MAX_PIPELINE, 100]);
+    }
+
+    #[tokio::test]
+    async fn lost_connection_is_reopened_and_the_pipeline_retried() {
+        let redis = FakeRedis::default();
+        let mut writer = connected_writer(&redis).await;
+        let reset = std::io::Error::new(std::io::ErrorKind::ConnectionReset, "reset");
+        redis.failures.lock().unwrap().push(reset.into());
+        publish(&mut writer, 3);
+        writer.flush().await.unwrap();
+        assert_eq!(*redis.pipelines.lock().unwrap(), [3, 3]);
+        assert_eq!(redis.connects.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn rejected_pipeline_is_discarded_without_reconnecting() {
+        let redis = FakeRedis::default();
+        let mut writer = connected_writer(&redis).await;
+        let wrong_type = (redis::ErrorKind::ResponseError, "WRONGTYPE").into();
+        redis.failures.lock().unwrap().push(wrong_type);
+        publish(&mut writer, 3);
+        writer.flush().await.unwrap();
+        publish(&mut writer, 2);
+        writer.flush().await.unwrap();
+        assert_eq!(*redis.pipelines.lock().unwrap(), [3, 2]);
+        assert_eq!(redis.connects.load(Ordering::SeqCst), 1);
+    }
+
+    // Needs a redis-server on 127.0.0.1:6379: `cargo test -- --ignored`.
+    #[tokio::test]
+    #[ignore]
+    async fn stream_sink_carries_on_after_redis_rejects_a_write() {
+        let url = "redis://127.0.0.1/";
+        let mut connection = Client::open(url)
+            .unwrap()
+            .get_multiplexed_async_connection()
+            .await
+            .unwrap();
+        let (taken, free) = ("backpack-test:SOL_USDC", "backpack-test:BTC_USDC");
+        connection.del::<_, ()>(&[taken, free]).await.unwrap();
+        // Every XADD to a key holding a string fails with WRONGTYPE.
+        connection
+            .set::<_, _, ()>(taken, "not a stream")
+            .await
+            .unwrap();
+
+        let mut sink = RedisStreamSink::open(url)
+            .unwrap()
+            .with_key("backpack-test:{symbol}");
+        timeout(Duration::from_secs(5), async {
+            while sink.flush().await.is_err() {
+                sleep(Duration::from_millis(10)).await;
+            }
+            for trade_id in 1..=10 {
+                sink.write(&trade("SOL_USDC", trade_id), 0).await.unwrap();
+                sink.write(&trade("BTC_USDC", trade_id), 0).await.unwrap();
+                sink.flush().await.unwrap();
+            }
+        })
+        .await
+        .expect("the writer got stuck on the rejected batch");
+
+        let written: usize = connection.xlen(free).await.unwrap();
+        assert_eq!(written, 10);
+        connection.del::<_, ()>(&[taken, free]).await.unwrap();
+    }
+}
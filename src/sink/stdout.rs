@@ -0,0 +1,42 @@
+use super::{ndjson_line, Sink};
+use crate::envelope::StreamEnvelope;
+use crate::error::SinkError;
+use crate::event_type::Event;
+use async_trait::async_trait;
+use tokio::io::{stdout, AsyncWriteExt, BufWriter, Stdout};
+
+pub struct StdoutSink {
+    out: BufWriter<Stdout>,
+}
+
+impl StdoutSink {
+    pub fn new() -> Self {
+        Self {
+            out: BufWriter::new(stdout()),
+        }
+    }
+}
+
+impl Default for StdoutSink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl Sink for StdoutSink {
+    async fn write(
+        &mut self,
+        envelope: &StreamEnvelope<Event>,
+        received_at: u64,
+    ) -> Result<(), SinkError> {
+        let line = ndjson_line(envelope, received_at)?;
+        self.out.write_all(line.as_bytes()).await?;
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        self.out.flush().await?;
+        Ok(())
+    }
+}
@@ -1,38 +1,61 @@
-use super::event_type::EventType;
+use super::error::ParseError;
+use super::event_type::{EventType, KlineInterval};
+use super::market::MarketRegistry;
+use super::response::{ErrorCode, Response};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::borrow::Borrow;
 use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub enum Symbol {
-    #[serde(rename = "SOL_USD")]
-    SolUsd,
-    #[serde(rename = "SOL_USDC")]
-    SolUsdc,
-}
-
-impl From<String> for Symbol {
-    fn from(symbol: String) -> Self {
-        match symbol.as_str() {
-            "SOL_USD" => Symbol::SolUsd,
-            "SOL_USDC" => Symbol::SolUsdc,
-            _ => panic!("Invalid symbol type"),
-        }
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(transparent)]
+pub struct Symbol(String);
+
+impl Symbol {
+    // Builds a symbol without checking it against the market registry.
+    pub fn new(symbol: impl Into<String>) -> Self {
+        Self(symbol.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Borrow<str> for Symbol {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for Symbol {
+    type Err = ParseError;
+
+    fn from_str(symbol: &str) -> Result<Self, Self::Err> {
+        MarketRegistry::global().read().unwrap().symbol(symbol)
+    }
+}
+
+impl TryFrom<String> for Symbol {
+    type Error = ParseError;
+
+    fn try_from(symbol: String) -> Result<Self, Self::Error> {
+        symbol.parse()
     }
 }
 
 impl Display for Symbol {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        match self {
-            Symbol::SolUsd => write!(f, "SOL_USD"),
-            Symbol::SolUsdc => write!(f, "SOL_USDC"),
-        }
+        write!(f, "{}", self.0)
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
 pub struct StreamName {
     pub stream: EventType,
-    pub interval: Option<String>,
+    pub interval: Option<KlineInterval>,
     pub symbol: Symbol,
 }
 
@@ -41,9 +64,32 @@ impl StreamName {
         Self {
             stream: EventType::BookTicker,
             interval: None,
-            symbol: Symbol::SolUsd,
+            symbol: Symbol::new("SOL_USD"),
         }
     }
+
+    // Stream names look like `depth.SOL_USDC`, or `kline.1m.SOL_USDC` for klines.
+    pub fn parse_with(stream_name: &str, registry: &MarketRegistry) -> Result<Self, ParseError> {
+        let segments: Vec<&str> = stream_name.split('.').collect();
+        let stream: EventType = segments[0].parse()?;
+        let (interval, symbol) = match (&stream, &segments[1..]) {
+            (_, []) => return Err(ParseError::MissingSymbol(stream_name.to_string())),
+            (EventType::Kline, [_]) => {
+                return Err(ParseError::MissingInterval(stream_name.to_string()))
+            }
+            (EventType::Kline, [interval, symbol]) => (Some(interval.parse()?), *symbol),
+            (EventType::Kline, _) => {
+                return Err(ParseError::ExtraSegments(stream_name.to_string()))
+            }
+            (_, [symbol]) => (None, *symbol),
+            (_, _) => return Err(ParseError::ExtraSegments(stream_name.to_string())),
+        };
+        Ok(StreamName {
+            stream,
+            interval,
+            symbol: registry.symbol(symbol)?,
+        })
+    }
 }
 
 impl Default for StreamName {
@@ -52,28 +98,25 @@ impl Default for StreamName {
     }
 }
 
-impl From<String> for StreamName {
-    fn from(stream_name: String) -> Self {
-        let mut stream_name = stream_name.split('.');
-        let len = stream_name.clone().count();
-        if len == 2 {
-            let stream: EventType = stream_name.next().unwrap().to_string().into();
-            let symbol: Symbol = stream_name.next().unwrap().to_string().into();
-            StreamName {
-                stream,
-                interval: None,
-                symbol,
-            }
-        } else {
-            let stream: EventType = stream_name.next().unwrap().to_string().into();
-            let interval: Option<String> = Some(stream_name.next().unwrap().to_string());
-            let symbol: Symbol = stream_name.next().unwrap().to_string().into();
-            StreamName {
-                stream,
-                interval,
-                symbol,
-            }
-        }
+impl FromStr for StreamName {
+    type Err = ParseError;
+
+    fn from_str(stream_name: &str) -> Result<Self, Self::Err> {
+        Self::parse_with(stream_name, &MarketRegistry::global().read().unwrap())
+    }
+}
+
+impl TryFrom<String> for StreamName {
+    type Error = ParseError;
+
+    fn try_from(stream_name: String) -> Result<Self, Self::Error> {
+        stream_name.parse()
+    }
+}
+
+impl From<StreamName> for String {
+    fn from(stream_name: StreamName) -> Self {
+        stream_name.to_string()
     }
 }
 
@@ -86,7 +129,7 @@ impl Display for StreamName {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Method {
     #[serde(rename = "SUBSCRIBE")]
     Subscribe,
@@ -94,18 +137,181 @@ pub enum Method {
     Unsubscribe,
 }
 
-impl From<String> for Method {
-    fn from(method: String) -> Self {
-        match method.as_str() {
-            "SUBSCRIBE" => Method::Subscribe,
-            "UNSUBSCRIBE" => Method::Unsubscribe,
-            _ => panic!("Invalid method type"),
+impl FromStr for Method {
+    type Err = ParseError;
+
+    fn from_str(method: &str) -> Result<Self, Self::Err> {
+        match method {
+            "SUBSCRIBE" => Ok(Method::Subscribe),
+            "UNSUBSCRIBE" => Ok(Method::Unsubscribe),
+            _ => Err(ParseError::UnknownMethod(method.to_string())),
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl TryFrom<String> for Method {
+    type Error = ParseError;
+
+    fn try_from(method: String) -> Result<Self, Self::Error> {
+        method.parse()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SubscribStream {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
     pub method: Method,
     pub params: Vec<StreamName>,
 }
+
+impl SubscribStream {
+    // Parses a request frame step by step so the error response can still echo the `id`
+    // and name the exact problem when the rest of the request is invalid.
+    pub fn from_request(text: &str) -> Result<Self, Response> {
+        let value = serde_json::from_str::<Value>(text)
+            .map_err(|e| Response::error(None, ErrorCode::MalformedRequest, e.to_string()))?;
+        let id = value.get("id").and_then(Value::as_u64);
+        let malformed = |message: &str| Response::error(id, ErrorCode::MalformedRequest, message);
+        if !value.is_object() {
+            return Err(malformed("request must be a JSON object"));
+        }
+        if value.get("id").is_some_and(|id| id.as_u64().is_none()) {
+            return Err(malformed("id must be an unsigned integer"));
+        }
+        let method = value
+            .get("method")
+            .and_then(Value::as_str)
+            .ok_or_else(|| malformed("missing method"))?
+            .parse::<Method>()
+            .map_err(|e| Response::error(id, ErrorCode::UnknownMethod, e.to_string()))?;
+        let params = value
+            .get("params")
+            .and_then(Value::as_array)
+            .ok_or_else(|| malformed("params must be an array of stream names"))?
+            .iter()
+            .map(|param| {
+                param
+                    .as_str()
+                    .ok_or_else(|| malformed("params must be an array of stream names"))?
+                    .parse::<StreamName>()
+                    .map_err(|e| {
+                        let code = match e {
+                            ParseError::UnknownSymbol(_) => ErrorCode::InvalidSymbol,
+                            _ => ErrorCode::InvalidStream,
+                        };
+                        Response::error(id, code, e.to_string())
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(SubscribStream { id, method, params })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(stream_name: &str) -> Result<StreamName, ParseError> {
+        StreamName::parse_with(stream_name, &MarketRegistry::with_defaults())
+    }
+
+    #[test]
+    fn parses_stream_names() {
+        let depth = parse("depth.SOL_USDC").unwrap();
+        assert_eq!(depth.stream, EventType::Depth);
+        assert_eq!(depth.interval, None);
+        assert_eq!(depth.symbol.as_str(), "SOL_USDC");
+        let kline = parse("kline.1w.BTC_USDC").unwrap();
+        assert_eq!(kline.stream, EventType::Kline);
+        assert_eq!(kline.interval, Some(KlineInterval::OneWeek));
+        assert_eq!(kline.to_string(), "kline.1w.BTC_USDC");
+    }
+
+    #[test]
+    fn rejects_malformed_stream_names() {
+        let cases = [
+            (
+                "trades.SOL_USDC",
+                ParseError::UnknownEventType("trades".into()),
+            ),
+            ("depth", ParseError::MissingSymbol("depth".into())),
+            (
+                "kline.SOL_USDC",
+                ParseError::MissingInterval("kline.SOL_USDC".into()),
+            ),
+            (
+                "kline.2m.SOL_USDC",
+                ParseError::UnknownInterval("2m".into()),
+            ),
+            (
+                "kline.1m.SOL_USDC.x",
+                ParseError::ExtraSegments("kline.1m.SOL_USDC.x".into()),
+            ),
+            (
+                "depth.SOL_USDC.x",
+                ParseError::ExtraSegments("depth.SOL_USDC.x".into()),
+            ),
+            (
+                "depth.JUP_USDC",
+                ParseError::UnknownSymbol("JUP_USDC".into()),
+            ),
+        ];
+        for (stream_name, error) in cases {
+            assert_eq!(parse(stream_name), Err(error), "{}", stream_name);
+        }
+    }
+
+    fn rejected(request: &str) -> (Option<u64>, ErrorCode) {
+        let response = SubscribStream::from_request(request).unwrap_err();
+        (response.id, response.error.unwrap().code)
+    }
+
+    #[test]
+    fn parses_requests() {
+        let request =
+            r#"{"id":3,"method":"SUBSCRIBE","params":["depth.SOL_USDC","trade.SOL_USDC"]}"#;
+        let request = SubscribStream::from_request(request).unwrap();
+        assert_eq!(request.id, Some(3));
+        assert!(matches!(request.method, Method::Subscribe));
+        assert_eq!(request.params.len(), 2);
+    }
+
+    #[test]
+    fn rejects_requests_with_the_matching_code() {
+        let cases = [
+            (
+                r#"{"id":1,"method":"LIST","params":[]}"#,
+                ErrorCode::UnknownMethod,
+            ),
+            (
+                r#"{"id":2,"method":"SUBSCRIBE","params":["trades.SOL_USDC"]}"#,
+                ErrorCode::InvalidStream,
+            ),
+            (
+                r#"{"id":3,"method":"SUBSCRIBE","params":["depth.JUP_USDC"]}"#,
+                ErrorCode::InvalidSymbol,
+            ),
+            (
+                r#"{"id":4,"method":"SUBSCRIBE","params":"depth.SOL_USDC"}"#,
+                ErrorCode::MalformedRequest,
+            ),
+        ];
+        for (id, (request, code)) in (1..).zip(cases) {
+            assert_eq!(rejected(request), (Some(id), code), "{}", request);
+        }
+    }
+
+    #[test]
+    fn error_without_a_usable_id_has_none() {
+        assert_eq!(rejected("not json"), (None, ErrorCode::MalformedRequest));
+        let request = r#"{"id":"one","method":"SUBSCRIBE","params":[]}"#;
+        assert_eq!(rejected(request), (None, ErrorCode::MalformedRequest));
+    }
+
+    #[test]
+    fn parses_against_the_given_registry() {
+        let registry = MarketRegistry::new();
+        assert!(StreamName::parse_with("depth.SOL_USDC", &registry).is_err());
+    }
+}